@@ -0,0 +1,124 @@
+//! Compares the work-stealing scheduler with the previous design, where every
+//! worker pulled jobs from one `Arc<Mutex<mpsc::Receiver>>`.
+//!
+//! Run with `cargo bench --bench scheduler`.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use multithreading::ThreadPool;
+
+const THREADS: usize = 8;
+const ROUNDS: usize = 5;
+
+/// The single-queue pool this crate used before the work-stealing scheduler.
+struct MutexQueuePool {
+    workers: Vec<thread::JoinHandle<()>>,
+    sender: Option<mpsc::Sender<Box<dyn FnOnce() + Send>>>,
+}
+
+impl MutexQueuePool {
+    fn new(size: usize) -> MutexQueuePool {
+        let (sender, receiver) = mpsc::channel::<Box<dyn FnOnce() + Send>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..size)
+            .map(|_| {
+                let receiver = Arc::clone(&receiver);
+                thread::spawn(move || loop {
+                    let job = receiver.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+            })
+            .collect();
+        MutexQueuePool {
+            workers,
+            sender: Some(sender),
+        }
+    }
+
+    fn execute<F: FnOnce() + Send + 'static>(&self, f: F) {
+        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+    }
+}
+
+impl Drop for MutexQueuePool {
+    fn drop(&mut self) {
+        self.sender.take();
+        for worker in self.workers.drain(..) {
+            worker.join().unwrap();
+        }
+    }
+}
+
+fn wait_for(counter: &AtomicUsize, expected: usize) {
+    while counter.load(Ordering::Acquire) < expected {
+        thread::yield_now();
+    }
+}
+
+fn small_task(counter: &AtomicUsize) {
+    let mut x = 0u64;
+    for i in 0..64 {
+        x = x.wrapping_mul(31).wrapping_add(i);
+    }
+    std::hint::black_box(x);
+    counter.fetch_add(1, Ordering::Release);
+}
+
+fn bench(name: &str, tasks: usize, mut run: impl FnMut(usize)) {
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        run(tasks);
+        best = best.min(start.elapsed());
+    }
+    println!(
+        "{:<40} {:>10.2?} ({:.0} ns/task)",
+        name,
+        best,
+        best.as_nanos() as f64 / tasks as f64
+    );
+}
+
+fn main() {
+    let tasks = 200_000;
+
+    let pool = ThreadPool::new(THREADS);
+    bench("work-stealing: external submissions", tasks, |tasks| {
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..tasks {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || small_task(&counter));
+        }
+        wait_for(&counter, tasks);
+    });
+    bench("work-stealing: nested submissions", tasks, |tasks| {
+        let counter = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for _ in 0..tasks / THREADS {
+                        s.spawn(|| small_task(&counter));
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.load(Ordering::Acquire), tasks);
+    });
+    drop(pool);
+
+    let pool = MutexQueuePool::new(THREADS);
+    bench("mutex queue: external submissions", tasks, |tasks| {
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0..tasks {
+            let counter = Arc::clone(&counter);
+            pool.execute(move || small_task(&counter));
+        }
+        wait_for(&counter, tasks);
+    });
+}
@@ -0,0 +1,206 @@
+//! Pinning workers to CPU cores, with the `affinity` feature.
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::ThreadPool;
+
+/// The cores a worker thread may run on, as reported by
+/// `ThreadPool::worker_affinity`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerAffinity {
+    pub id: usize,
+    pub cores: Vec<usize>,
+}
+
+/// How `ThreadPoolBuilder` pins workers to cores.
+#[derive(Clone)]
+pub(crate) enum Pinning {
+    /// Each worker on its own core, in the order of the cores the building
+    /// thread may run on, wrapping around when there are more workers.
+    PerCore,
+    /// Worker `id` on `cores[id % cores.len()]`.
+    Cores(Vec<usize>),
+    /// Worker `id` on the cores the closure returns for it.
+    With(Arc<dyn Fn(usize) -> Vec<usize> + Send + Sync>),
+}
+
+impl fmt::Debug for Pinning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pinning::PerCore => f.write_str("PerCore"),
+            Pinning::Cores(cores) => f.debug_tuple("Cores").field(cores).finish(),
+            Pinning::With(_) => f.write_str("With(..)"),
+        }
+    }
+}
+
+impl Pinning {
+    /// Turns `PerCore` into the list of cores it stands for, so that workers
+    /// spawned later by a pinned worker don't all land on its core.
+    pub(crate) fn resolve(self) -> io::Result<Pinning> {
+        let pinning = match self {
+            Pinning::PerCore => Pinning::Cores(sys::get(sys::thread_id())?),
+            pinning => pinning,
+        };
+        if matches!(&pinning, Pinning::Cores(cores) if cores.is_empty()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no cores to pin workers to",
+            ));
+        }
+        Ok(pinning)
+    }
+
+    fn cores(&self, id: usize) -> Vec<usize> {
+        match self {
+            Pinning::PerCore => unreachable!("pinning is resolved by build"),
+            Pinning::Cores(cores) => vec![cores[id % cores.len()]],
+            Pinning::With(f) => f(id),
+        }
+    }
+
+    /// Pins the calling thread, worker `id`, to its cores.
+    pub(crate) fn pin(&self, id: usize) -> io::Result<()> {
+        sys::set(sys::thread_id(), &self.cores(id))
+    }
+}
+
+/// The OS id of the calling thread, to query its affinity from elsewhere.
+pub(crate) fn thread_id() -> ThreadId {
+    sys::thread_id()
+}
+
+pub(crate) use sys::ThreadId;
+
+impl ThreadPool {
+    /// The cores each live worker may currently run on, whether or not the
+    /// pool pinned it.
+    ///
+    /// Returns an error with `io::ErrorKind::Unsupported` on platforms
+    /// other than Linux.
+    pub fn worker_affinity(&self) -> io::Result<Vec<WorkerAffinity>> {
+        let workers = self.shared.workers.lock().unwrap();
+        let mut affinity = Vec::with_capacity(workers.len());
+        for worker in workers.iter() {
+            if worker
+                .thread
+                .as_ref()
+                .is_none_or(|thread| thread.is_finished())
+            {
+                continue;
+            }
+            match sys::get(worker.thread_id) {
+                Ok(cores) => affinity.push(WorkerAffinity {
+                    id: worker.id,
+                    cores,
+                }),
+                // The worker exited after the check above.
+                Err(err) if err.raw_os_error() == Some(sys::NO_SUCH_THREAD) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(affinity)
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+    use std::mem;
+
+    pub(super) use crate::os::thread_id;
+
+    pub(crate) type ThreadId = libc::pid_t;
+
+    pub(super) const NO_SUCH_THREAD: i32 = libc::ESRCH;
+
+    pub(super) fn set(thread: ThreadId, cores: &[usize]) -> io::Result<()> {
+        // SAFETY: an all-zero cpu_set_t is the empty set.
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        for &core in cores {
+            if core >= libc::CPU_SETSIZE as usize {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("core {} is out of range", core),
+                ));
+            }
+            // SAFETY: `core` was checked to be within the set.
+            unsafe { libc::CPU_SET(core, &mut set) };
+        }
+        // SAFETY: `set` is a valid cpu_set_t of the size passed.
+        let result =
+            unsafe { libc::sched_setaffinity(thread, mem::size_of::<libc::cpu_set_t>(), &set) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(super) fn get(thread: ThreadId) -> io::Result<Vec<usize>> {
+        // SAFETY: as in `set`.
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        let result =
+            unsafe { libc::sched_getaffinity(thread, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cores = 0..libc::CPU_SETSIZE as usize;
+        // SAFETY: every core in the range is within the set.
+        Ok(cores
+            .filter(|&core| unsafe { libc::CPU_ISSET(core, &set) })
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    pub(crate) type ThreadId = u32;
+
+    pub(super) const NO_SUCH_THREAD: i32 = 0;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "CPU affinity is only supported on Linux",
+        )
+    }
+
+    pub(super) fn thread_id() -> ThreadId {
+        0
+    }
+
+    pub(super) fn set(_thread: ThreadId, _cores: &[usize]) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn get(_thread: ThreadId) -> io::Result<Vec<usize>> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::ThreadPoolBuilder;
+
+    #[test]
+    fn pinned_workers_report_their_cores() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .pin_to_cores([0])
+            .build()
+            .unwrap();
+        let affinity = pool.worker_affinity().unwrap();
+        assert_eq!(affinity.len(), 2);
+        assert!(affinity.iter().all(|worker| worker.cores == [0]));
+
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .pin_with(|_| vec![usize::MAX])
+            .build();
+        assert!(matches!(pool, Err(crate::BuildError::Affinity(_))));
+    }
+}
@@ -0,0 +1,135 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What `execute` does when a pool built with a `queue_capacity` is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy {
+    /// Block the caller until a worker takes a job off the queue.
+    ///
+    /// A job submitted from one of the pool's own workers runs on that worker
+    /// instead, since blocking it could leave nobody to drain the queue.
+    #[default]
+    Block,
+    /// Fail with `ExecuteError::Full`.
+    ///
+    /// Jobs from `scope`, `fork_join` and `run_graph` run on the caller's
+    /// thread instead, as with `CallerRuns`, since their caller waits for
+    /// them anyway.
+    Reject,
+    /// Run the job on the caller's thread.
+    CallerRuns,
+    /// Discard the oldest queued job of the lowest priority to make room.
+    ///
+    /// Jobs from `scope`, `fork_join` and `run_graph` are never discarded,
+    /// since their caller waits for them. If one is the oldest, it runs on
+    /// the caller's thread instead.
+    DropOldest,
+}
+
+/// How often each backpressure policy kicked in, from `ThreadPool::backpressure_stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BackpressureStats {
+    /// Submissions that had to wait for room in the queue.
+    pub blocked: u64,
+    /// Submissions that were rejected.
+    pub rejected: u64,
+    /// Jobs that ran on the submitting thread.
+    pub caller_runs: u64,
+    /// Queued jobs that were discarded to make room.
+    pub dropped_oldest: u64,
+}
+
+#[derive(Default)]
+pub(crate) struct Counters {
+    blocked: AtomicU64,
+    rejected: AtomicU64,
+    caller_runs: AtomicU64,
+    dropped_oldest: AtomicU64,
+}
+
+impl Counters {
+    pub(crate) fn record(&self, outcome: BackpressurePolicy) {
+        let counter = match outcome {
+            BackpressurePolicy::Block => &self.blocked,
+            BackpressurePolicy::Reject => &self.rejected,
+            BackpressurePolicy::CallerRuns => &self.caller_runs,
+            BackpressurePolicy::DropOldest => &self.dropped_oldest,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> BackpressureStats {
+        BackpressureStats {
+            blocked: self.blocked.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            caller_runs: self.caller_runs.load(Ordering::Relaxed),
+            dropped_oldest: self.dropped_oldest.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::block_worker;
+    use crate::{ExecuteError, ThreadPool, ThreadPoolBuilder};
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// A one-worker pool with room for one queued job, whose worker is busy
+    /// until the returned sender is used.
+    fn full_pool(policy: BackpressurePolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .backpressure(policy)
+            .build()
+            .unwrap();
+        let release = block_worker(&pool);
+        pool.execute(|| {});
+        (pool, release)
+    }
+
+    #[test]
+    fn reject_hands_the_job_back() {
+        let (pool, release) = full_pool(BackpressurePolicy::Reject);
+        let err = pool.try_execute(|| {}).unwrap_err();
+        assert!(matches!(err, ExecuteError::Full(_)));
+        assert_eq!(pool.backpressure_stats().rejected, 1);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn caller_runs_on_the_submitting_thread() {
+        let (pool, release) = full_pool(BackpressurePolicy::CallerRuns);
+        let caller = thread::current().id();
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(thread::current().id()).unwrap());
+        assert_eq!(rx.recv().unwrap(), caller);
+        assert_eq!(pool.backpressure_stats().caller_runs, 1);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn drop_oldest_discards_the_queued_job() {
+        let (pool, release) = full_pool(BackpressurePolicy::DropOldest);
+        let handle = pool.submit(|| 1);
+        let newest = pool.submit(|| 2);
+        release.send(()).unwrap();
+        assert!(handle.join().is_err());
+        assert_eq!(newest.join().unwrap(), 2);
+        assert_eq!(pool.backpressure_stats().dropped_oldest, 2);
+    }
+
+    #[test]
+    fn block_waits_for_room() {
+        let (pool, release) = full_pool(BackpressurePolicy::Block);
+        thread::scope(|s| {
+            s.spawn(|| pool.execute(|| {}));
+            while pool.backpressure_stats().blocked == 0 {
+                thread::yield_now();
+            }
+            release.send(()).unwrap();
+        });
+        assert_eq!(pool.backpressure_stats().blocked, 1);
+    }
+}
@@ -0,0 +1,360 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "affinity")]
+use crate::affinity::Pinning;
+use crate::observer::DebugObserver;
+#[cfg(feature = "thread-priority")]
+use crate::thread_priority::{SchedPolicy, ThreadPriority};
+use crate::{BackpressurePolicy, PoolObserver};
+use crate::{Shared, ThreadPool};
+
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(60);
+const DEFAULT_NAME: &str = "pool";
+
+/// Configures and spawns a `ThreadPool`.
+///
+/// ```
+/// use multithreading::ThreadPoolBuilder;
+///
+/// let pool = ThreadPoolBuilder::new()
+///     .num_threads(4)
+///     .thread_name(|id| format!("worker-{}", id))
+///     .build()
+///     .unwrap();
+/// pool.execute(|| println!("hello from the pool"));
+/// ```
+#[derive(Clone, Default)]
+pub struct ThreadPoolBuilder {
+    name: Option<String>,
+    num_threads: Option<usize>,
+    thread_name: Option<fn(usize) -> String>,
+    stack_size: Option<usize>,
+    pub(crate) queue_capacity: Option<usize>,
+    pub(crate) backpressure: BackpressurePolicy,
+    max_threads: Option<usize>,
+    keep_alive: Option<Duration>,
+    pub(crate) priority_aging: Option<Duration>,
+    #[cfg(feature = "affinity")]
+    pub(crate) pinning: Option<Pinning>,
+    #[cfg(feature = "thread-priority")]
+    pub(crate) thread_priority: ThreadPriority,
+    debug: bool,
+    observers: Vec<Arc<dyn PoolObserver>>,
+}
+
+impl fmt::Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ThreadPoolBuilder");
+        debug
+            .field("name", &self.name)
+            .field("num_threads", &self.num_threads)
+            .field("thread_name", &self.thread_name)
+            .field("stack_size", &self.stack_size)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("backpressure", &self.backpressure)
+            .field("max_threads", &self.max_threads)
+            .field("keep_alive", &self.keep_alive)
+            .field("priority_aging", &self.priority_aging);
+        #[cfg(feature = "affinity")]
+        debug.field("pinning", &self.pinning);
+        #[cfg(feature = "thread-priority")]
+        debug.field("thread_priority", &self.thread_priority);
+        debug
+            .field("debug", &self.debug)
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+impl ThreadPoolBuilder {
+    pub fn new() -> ThreadPoolBuilder {
+        ThreadPoolBuilder::default()
+    }
+
+    /// Number of worker threads. Defaults to `std::thread::available_parallelism`.
+    ///
+    /// With `max_threads`, this is the number of core workers that are kept
+    /// alive even when idle.
+    pub fn num_threads(mut self, num_threads: usize) -> ThreadPoolBuilder {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Names the pool. Worker threads are called `"{name}-worker-{id}"`
+    /// unless `thread_name` is set, and the timer thread `"{name}-timer"`.
+    /// Defaults to `"pool"`.
+    pub fn name(mut self, name: impl Into<String>) -> ThreadPoolBuilder {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Names each worker thread from its id, instead of the default
+    /// `"{name}-worker-{id}"`.
+    pub fn thread_name(mut self, thread_name: fn(usize) -> String) -> ThreadPoolBuilder {
+        self.thread_name = Some(thread_name);
+        self
+    }
+
+    /// Stack size in bytes for each worker thread.
+    pub fn stack_size(mut self, stack_size: usize) -> ThreadPoolBuilder {
+        self.stack_size = Some(stack_size);
+        self
+    }
+
+    /// Limits how many jobs may wait in the queue. Unbounded by default.
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// What to do when the queue is full. Defaults to `BackpressurePolicy::Block`.
+    pub fn backpressure(mut self, policy: BackpressurePolicy) -> ThreadPoolBuilder {
+        self.backpressure = policy;
+        self
+    }
+
+    /// Makes the pool elastic: when jobs back up in the queue, extra workers
+    /// are spawned until the pool has `max_threads` in total. Extra workers
+    /// exit after being idle for `keep_alive`.
+    pub fn max_threads(mut self, max_threads: usize) -> ThreadPoolBuilder {
+        self.max_threads = Some(max_threads);
+        self
+    }
+
+    /// How long an extra worker of an elastic pool waits for a job before
+    /// exiting. Defaults to 60 seconds; ignored without `max_threads`.
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Lets lower priority jobs age: once a priority level has had jobs
+    /// waiting for `aging` without a worker taking one, its next job runs
+    /// ahead of higher priority ones. Off by default, so a steady stream of
+    /// high priority jobs can delay the others indefinitely.
+    pub fn priority_aging(mut self, aging: Duration) -> ThreadPoolBuilder {
+        self.priority_aging = Some(aging);
+        self
+    }
+
+    /// Pins each worker to a core of its own, going through the cores this
+    /// thread may run on in order. With more workers than cores, they wrap
+    /// around and share.
+    #[cfg(feature = "affinity")]
+    pub fn pin_one_per_core(mut self) -> ThreadPoolBuilder {
+        self.pinning = Some(Pinning::PerCore);
+        self
+    }
+
+    /// Pins worker `id` to `cores[id % cores.len()]`.
+    #[cfg(feature = "affinity")]
+    pub fn pin_to_cores(mut self, cores: impl IntoIterator<Item = usize>) -> ThreadPoolBuilder {
+        self.pinning = Some(Pinning::Cores(cores.into_iter().collect()));
+        self
+    }
+
+    /// Pins each worker to the cores `cores` returns for its id.
+    #[cfg(feature = "affinity")]
+    pub fn pin_with<F>(mut self, cores: F) -> ThreadPoolBuilder
+    where
+        F: Fn(usize) -> Vec<usize> + Send + Sync + 'static,
+    {
+        self.pinning = Some(Pinning::With(Arc::new(cores)));
+        self
+    }
+
+    /// The nice value of each worker thread, from -20 (highest priority) to
+    /// 19 (lowest). Raising it is always allowed; lowering it below the
+    /// process's usually needs `CAP_SYS_NICE`. Linux only.
+    #[cfg(feature = "thread-priority")]
+    pub fn nice(mut self, nice: i32) -> ThreadPoolBuilder {
+        self.thread_priority.nice = Some(nice);
+        self
+    }
+
+    /// The scheduling policy of each worker thread. Linux only.
+    #[cfg(feature = "thread-priority")]
+    pub fn sched_policy(mut self, policy: SchedPolicy) -> ThreadPoolBuilder {
+        self.thread_priority.policy = Some(policy);
+        self
+    }
+
+    /// Prints worker activity to stdout, like `ThreadPool::new_with_debug`.
+    pub fn debug(mut self, debug: bool) -> ThreadPoolBuilder {
+        self.debug = debug;
+        self
+    }
+
+    /// Adds an observer that is told about worker and job events. May be
+    /// called more than once.
+    pub fn observer(mut self, observer: Arc<dyn PoolObserver>) -> ThreadPoolBuilder {
+        self.observers.push(observer);
+        self
+    }
+
+    /// The upper limit on workers and their idle keep-alive, if the pool is
+    /// elastic.
+    pub(crate) fn elastic(&self) -> Option<(usize, Duration)> {
+        let max_threads = self.max_threads?;
+        Some((max_threads, self.keep_alive.unwrap_or(DEFAULT_KEEP_ALIVE)))
+    }
+
+    pub(crate) fn observers(&self) -> Vec<Arc<dyn PoolObserver>> {
+        let mut observers = self.observers.clone();
+        if self.debug {
+            observers.push(Arc::new(DebugObserver));
+        }
+        #[cfg(feature = "log")]
+        observers.push(Arc::new(crate::instrument::LogObserver));
+        #[cfg(feature = "tracing")]
+        observers.push(Arc::new(crate::instrument::TracingObserver));
+        observers
+    }
+
+    /// Spawns the workers.
+    ///
+    /// If a thread fails to spawn, the workers that were already started are
+    /// shut down before the error is returned.
+    pub fn build(self) -> Result<ThreadPool, BuildError> {
+        let size = match self.num_threads {
+            Some(0) => return Err(BuildError::ZeroThreads),
+            Some(size) => size,
+            None => thread::available_parallelism().map_or(1, |n| n.get()),
+        };
+        if self.queue_capacity == Some(0) {
+            return Err(BuildError::ZeroCapacity);
+        }
+        if self.max_threads.is_some_and(|max| max < size) {
+            return Err(BuildError::MaxBelowCore);
+        }
+        #[cfg(feature = "affinity")]
+        let pinning = self.pinning.clone().map(Pinning::resolve).transpose();
+        #[cfg(feature = "affinity")]
+        let builder = ThreadPoolBuilder {
+            pinning: pinning.map_err(BuildError::Affinity)?,
+            ..self
+        };
+        #[cfg(not(feature = "affinity"))]
+        let builder = self;
+
+        let pool = ThreadPool {
+            shared: Arc::new(Shared::new(builder)),
+            owned: true,
+        };
+        pool.resize(size)?;
+        Ok(pool)
+    }
+
+    pub(crate) fn pool_name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_NAME)
+    }
+
+    pub(crate) fn thread_builder(&self, id: usize) -> thread::Builder {
+        let name = match self.thread_name {
+            Some(thread_name) => thread_name(id),
+            None => format!("{}-worker-{}", self.pool_name(), id),
+        };
+        let mut builder = thread::Builder::new().name(name);
+        if let Some(stack_size) = self.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        builder
+    }
+}
+
+/// Returned by `ThreadPoolBuilder::build` when the pool can't be created.
+#[derive(Debug)]
+pub enum BuildError {
+    /// `num_threads` was set to zero.
+    ZeroThreads,
+    /// `queue_capacity` was set to zero.
+    ZeroCapacity,
+    /// `max_threads` was set below `num_threads`.
+    MaxBelowCore,
+    /// The operating system refused to spawn a worker thread.
+    Spawn(io::Error),
+    /// A worker couldn't be pinned to its cores.
+    Affinity(io::Error),
+    /// A worker's nice value or scheduling policy couldn't be set.
+    ThreadPriority(io::Error),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::ZeroThreads => f.write_str("a thread pool needs at least one thread"),
+            BuildError::ZeroCapacity => {
+                f.write_str("a bounded queue needs room for at least one job")
+            }
+            BuildError::MaxBelowCore => {
+                f.write_str("max_threads must be at least as large as num_threads")
+            }
+            BuildError::Spawn(err) => write!(f, "failed to spawn worker thread: {}", err),
+            BuildError::Affinity(err) => write!(f, "failed to pin worker thread: {}", err),
+            BuildError::ThreadPriority(err) => {
+                write!(f, "failed to set worker thread priority: {}", err)
+            }
+        }
+    }
+}
+
+impl Error for BuildError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BuildError::ZeroThreads | BuildError::ZeroCapacity | BuildError::MaxBelowCore => None,
+            BuildError::Spawn(err)
+            | BuildError::Affinity(err)
+            | BuildError::ThreadPriority(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_threads_is_an_error() {
+        let result = ThreadPoolBuilder::new().num_threads(0).build();
+        assert!(matches!(result, Err(BuildError::ZeroThreads)));
+    }
+
+    #[test]
+    fn max_threads_below_core_is_an_error() {
+        let result = ThreadPoolBuilder::new()
+            .num_threads(4)
+            .max_threads(2)
+            .build();
+        assert!(matches!(result, Err(BuildError::MaxBelowCore)));
+    }
+
+    #[test]
+    fn names_worker_threads() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .thread_name(|id| format!("test-worker-{}", id))
+            .build()
+            .unwrap();
+        let name = pool
+            .submit(|| thread::current().name().map(String::from))
+            .join()
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("test-worker-0"));
+
+        let pool = ThreadPoolBuilder::new()
+            .name("io")
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let name = pool
+            .submit(|| thread::current().name().map(String::from))
+            .join()
+            .unwrap();
+        assert_eq!(name.as_deref(), Some("io-worker-0"));
+    }
+}
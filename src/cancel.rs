@@ -0,0 +1,88 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag that tells a job it should stop, shared between the job and its
+/// `TaskHandle`.
+///
+/// Cancellation is cooperative: a job started with
+/// `ThreadPool::submit_cancellable` gets the token and decides itself when
+/// to check it. A job cancelled before it started is skipped.
+///
+/// ```
+/// use multithreading::{TaskError, ThreadPool};
+///
+/// let pool = ThreadPool::new(1);
+/// let handle = pool.submit_cancellable(|token| {
+///     let mut steps = 0;
+///     while !token.is_cancelled() && steps < 1_000_000 {
+///         steps += 1;
+///     }
+///     steps
+/// });
+/// handle.cancel();
+/// match handle.join() {
+///     Ok(steps) => assert!(steps <= 1_000_000),
+///     Err(err) => assert!(matches!(err, TaskError::Cancelled)),
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    /// Asks every holder of this token to stop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::block_worker;
+    use crate::{TaskError, ThreadPool};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    #[test]
+    fn cancelled_tasks_are_skipped() {
+        let pool = ThreadPool::new(1);
+        let release = block_worker(&pool);
+
+        let ran = Arc::new(AtomicBool::new(false));
+        let handle = pool.submit({
+            let ran = Arc::clone(&ran);
+            move || ran.store(true, Ordering::SeqCst)
+        });
+        handle.cancel();
+        release.send(()).unwrap();
+        pool.wait_idle();
+
+        assert!(matches!(handle.join(), Err(TaskError::Cancelled)));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn running_tasks_see_the_token() {
+        let pool = ThreadPool::new(1);
+        let (started_tx, started_rx) = mpsc::channel();
+        let handle = pool.submit_cancellable(move |token| {
+            started_tx.send(()).unwrap();
+            while !token.is_cancelled() {
+                std::thread::yield_now();
+            }
+            "stopped early"
+        });
+        started_rx.recv().unwrap();
+        handle.cancel();
+        assert_eq!(handle.join().unwrap(), "stopped early");
+    }
+}
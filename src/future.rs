@@ -0,0 +1,275 @@
+//! A runtime-agnostic bridge between async code and the pool.
+//!
+//! `spawn_async` hands a blocking closure to the workers and returns a
+//! future that any executor can await. `spawn_future` and `block_on` go the
+//! other way and poll a future on the workers themselves.
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::task::{self, Completer};
+use crate::{worker, Shared, TaskError, TaskHandle, ThreadPool};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+impl ThreadPool {
+    /// Runs `f` on the pool and returns a future for its result.
+    ///
+    /// The future is woken when the job finishes, so it works with any
+    /// executor. It's the future counterpart of `submit`; use that, and
+    /// await the `TaskHandle`, to get a `TaskError` instead of a panic.
+    ///
+    /// # Panics
+    ///
+    /// Awaiting the future resumes the job's panic if it panicked, and
+    /// panics if the pool dropped the job without running it.
+    pub fn spawn_async<F, T>(&self, f: F) -> impl Future<Output = T> + Send + 'static
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let handle = self.submit(f);
+        async move { unwrap_task(handle.await) }
+    }
+
+    /// Polls `future` on the pool's workers until it completes.
+    ///
+    /// Each poll runs as a job. When the future is woken, another poll is
+    /// queued, even if a bounded queue is full: backpressure never rejects
+    /// or discards a poll. Cancelling the handle drops the future before its
+    /// next poll.
+    pub fn spawn_future<F>(&self, future: F) -> TaskHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (completer, handle) = task::task();
+        let task = Arc::new(FutureTask {
+            shared: Arc::downgrade(&self.shared),
+            state: Mutex::new(Some((Box::pin(future), completer))),
+            queued: AtomicBool::new(false),
+        });
+        task.schedule();
+        handle
+    }
+
+    /// Runs `future` on the pool's workers and blocks until it completes.
+    ///
+    /// # Panics
+    ///
+    /// If the future panics, with the same payload, or if the pool shut
+    /// down before the future completed.
+    pub fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        unwrap_task(self.spawn_future(future).join())
+    }
+}
+
+fn unwrap_task<T>(result: Result<T, TaskError>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(TaskError::Panicked(payload)) => panic::resume_unwind(payload),
+        Err(err) => panic!("pool task failed: {}", err),
+    }
+}
+
+/// A future being polled by `spawn_future`, and its own waker.
+struct FutureTask<T> {
+    shared: Weak<Shared>,
+    /// `None` once the future has completed or been dropped.
+    state: Mutex<Option<(BoxFuture<T>, Completer<T>)>>,
+    /// Whether a poll is queued and hasn't started yet, so a burst of
+    /// wake-ups queues only one.
+    queued: AtomicBool,
+}
+
+impl<T: Send + 'static> FutureTask<T> {
+    /// Queues a poll of the future. If the pool no longer accepts jobs, the
+    /// future is dropped, which resolves its handle with `TaskError::Dropped`.
+    fn schedule(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let job = PollJob(Some(Arc::clone(self)));
+        if let Some(shared) = self.shared.upgrade() {
+            let _ = shared.submit_resumed(move || job.run());
+        }
+    }
+
+    fn poll(self: Arc<Self>) {
+        self.queued.store(false, Ordering::SeqCst);
+        let mut state = self.state.lock().unwrap();
+        let Some((future, completer)) = state.as_mut() else {
+            return;
+        };
+        if completer.is_cancelled() {
+            let (_, completer) = state.take().unwrap();
+            return completer.complete(Err(TaskError::Cancelled));
+        }
+
+        let waker = Waker::from(Arc::clone(&self));
+        let mut cx = Context::from_waker(&waker);
+        let poll = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx)));
+        let result = match poll {
+            Ok(Poll::Pending) => return,
+            Ok(Poll::Ready(value)) => Ok(value),
+            Err(payload) => {
+                worker::report_caught_panic();
+                Err(TaskError::Panicked(payload))
+            }
+        };
+        let (_, completer) = state.take().unwrap();
+        completer.complete(result);
+    }
+}
+
+impl<T> FutureTask<T> {
+    /// Drops the future after a poll was dropped without running.
+    fn abandon(&self) {
+        self.queued.store(false, Ordering::SeqCst);
+        // A poll in progress on this thread may hold the lock; dropping the
+        // last waker drops the future then instead.
+        if let Ok(mut state) = self.state.try_lock() {
+            state.take();
+        }
+    }
+}
+
+/// A queued poll of a `FutureTask`. If it's dropped without running, say
+/// because the pool refused it or `shutdown_now` discarded it, the future
+/// is dropped too, so that its handle resolves.
+struct PollJob<T>(Option<Arc<FutureTask<T>>>);
+
+impl<T: Send + 'static> PollJob<T> {
+    fn run(mut self) {
+        self.0.take().unwrap().poll();
+    }
+}
+
+impl<T> Drop for PollJob<T> {
+    fn drop(&mut self) {
+        if let Some(task) = self.0.take() {
+            task.abandon();
+        }
+    }
+}
+
+impl<T: Send + 'static> Wake for FutureTask<T> {
+    fn wake(self: Arc<Self>) {
+        self.schedule();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.schedule();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::block_worker;
+    use crate::{BackpressurePolicy, TaskError, ThreadPool, ThreadPoolBuilder};
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::mpsc;
+    use std::task::{Context, Poll, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Returns `Pending` once, waking itself from another thread.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                return Poll::Ready(());
+            }
+            self.0 = true;
+            let waker = cx.waker().clone();
+            thread::spawn(move || waker.wake());
+            Poll::Pending
+        }
+    }
+
+    /// Returns `Pending` once, handing its waker to the test.
+    struct Gate(Option<mpsc::Sender<Waker>>);
+
+    impl Future for Gate {
+        type Output = u32;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<u32> {
+            match self.0.take() {
+                Some(wakers) => {
+                    wakers.send(cx.waker().clone()).unwrap();
+                    Poll::Pending
+                }
+                None => Poll::Ready(7),
+            }
+        }
+    }
+
+    #[test]
+    fn spawn_async_is_woken_when_the_job_finishes() {
+        let pool = ThreadPool::new(2);
+        let (tx, rx) = mpsc::channel::<()>();
+        let future = pool.spawn_async(move || {
+            rx.recv().unwrap();
+            6 * 7
+        });
+        tx.send(()).unwrap();
+        // Awaited from a future that itself runs on the pool.
+        assert_eq!(pool.block_on(future), 42);
+    }
+
+    #[test]
+    fn spawn_future_repolls_after_a_wake() {
+        let pool = ThreadPool::new(1);
+        let handle = pool.spawn_future(async {
+            YieldOnce(false).await;
+            YieldOnce(false).await;
+            "done"
+        });
+        assert_eq!(handle.join().unwrap(), "done");
+
+        let panicked = pool.spawn_future(async { panic!("in a future") });
+        assert!(matches!(panicked.join(), Err(TaskError::Panicked(_))));
+    }
+
+    #[test]
+    fn wakes_get_past_a_full_queue() {
+        for policy in [BackpressurePolicy::Reject, BackpressurePolicy::DropOldest] {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(1)
+                .queue_capacity(1)
+                .backpressure(policy)
+                .build()
+                .unwrap();
+            let (wakers, waker) = mpsc::channel();
+            let handle = pool.spawn_future(Gate(Some(wakers)));
+            let waker = waker.recv().unwrap();
+
+            let release = block_worker(&pool);
+            pool.execute(|| {});
+            waker.wake_by_ref();
+            if policy == BackpressurePolicy::DropOldest {
+                // Evicts the `execute` job, then runs the poll here.
+                pool.execute(|| {});
+            }
+            waker.wake();
+            release.send(()).unwrap();
+
+            let result = handle
+                .join_timeout(Duration::from_secs(5))
+                .unwrap_or_else(|_| panic!("the future was never polled with {:?}", policy));
+            assert_eq!(result.unwrap(), 7, "{:?}", policy);
+        }
+    }
+}
@@ -0,0 +1,453 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::ops::Index;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+
+use crate::{worker, Shared, ThreadPool};
+
+type NodeFn<T, E> = Box<dyn FnOnce() -> Result<T, E> + Send + 'static>;
+
+/// Identifies a node of a `TaskGraph`, and its result in `GraphResults`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// The node's position, in the order the nodes were added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+struct Node<T, E> {
+    run: NodeFn<T, E>,
+    dependents: Vec<usize>,
+    dependencies: usize,
+}
+
+/// Builds a `TaskGraph` out of closures and the dependencies between them.
+///
+/// ```
+/// use multithreading::{NodeResult, TaskGraphBuilder, ThreadPool};
+///
+/// let pool = ThreadPool::new(2);
+/// let mut graph = TaskGraphBuilder::<&str, ()>::new();
+/// let a = graph.add_node(|| Ok("a"));
+/// let b = graph.add_node(|| Ok("b"));
+/// let c = graph.add_node(|| Ok("c"));
+/// graph.add_dependency(c, a);
+/// graph.add_dependency(c, b);
+///
+/// let results = pool.run_graph(graph.build().unwrap());
+/// assert!(matches!(results[c], NodeResult::Ok("c")));
+/// ```
+pub struct TaskGraphBuilder<T, E> {
+    nodes: Vec<Node<T, E>>,
+}
+
+impl<T, E> TaskGraphBuilder<T, E> {
+    pub fn new() -> TaskGraphBuilder<T, E> {
+        TaskGraphBuilder { nodes: Vec::new() }
+    }
+
+    /// Adds a node. A node that returns `Err` or panics fails, and every node
+    /// that depends on it, directly or not, is cancelled.
+    pub fn add_node<F>(&mut self, f: F) -> NodeId
+    where
+        F: FnOnce() -> Result<T, E> + Send + 'static,
+    {
+        self.nodes.push(Node {
+            run: Box::new(f),
+            dependents: Vec::new(),
+            dependencies: 0,
+        });
+        NodeId(self.nodes.len() - 1)
+    }
+
+    /// Makes `node` wait for `dependency` to succeed before it runs.
+    ///
+    /// # Panics
+    ///
+    /// If either id comes from a different builder.
+    pub fn add_dependency(&mut self, node: NodeId, dependency: NodeId) {
+        assert!(
+            node.0 < self.nodes.len() && dependency.0 < self.nodes.len(),
+            "NodeId from a different TaskGraphBuilder"
+        );
+        self.nodes[dependency.0].dependents.push(node.0);
+        self.nodes[node.0].dependencies += 1;
+    }
+
+    /// Checks that the dependencies don't form a cycle.
+    pub fn build(self) -> Result<TaskGraph<T, E>, GraphError> {
+        let mut waiting: Vec<usize> = self.nodes.iter().map(|node| node.dependencies).collect();
+        let mut ready: Vec<usize> = (0..self.nodes.len())
+            .filter(|&node| waiting[node] == 0)
+            .collect();
+        let mut ordered = 0;
+        while let Some(node) = ready.pop() {
+            ordered += 1;
+            for &dependent in &self.nodes[node].dependents {
+                waiting[dependent] -= 1;
+                if waiting[dependent] == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+        if ordered < self.nodes.len() {
+            let stuck = (0..self.nodes.len())
+                .filter(|&node| waiting[node] > 0)
+                .map(NodeId)
+                .collect();
+            return Err(GraphError::Cycle(stuck));
+        }
+        Ok(TaskGraph { nodes: self.nodes })
+    }
+}
+
+impl<T, E> Default for TaskGraphBuilder<T, E> {
+    fn default() -> TaskGraphBuilder<T, E> {
+        TaskGraphBuilder::new()
+    }
+}
+
+/// A set of closures with dependencies between them and no cycles, ready
+/// for `ThreadPool::run_graph`.
+pub struct TaskGraph<T, E> {
+    nodes: Vec<Node<T, E>>,
+}
+
+impl<T, E> TaskGraph<T, E> {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+}
+
+/// Returned by `TaskGraphBuilder::build` when the graph can't be run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// The dependencies form a cycle. Holds the nodes that are on a cycle
+    /// or depend on one.
+    Cycle(Vec<NodeId>),
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::Cycle(nodes) => {
+                write!(f, "{} task graph nodes depend on a cycle", nodes.len())
+            }
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+/// What happened to one node of a graph.
+pub enum NodeResult<T, E> {
+    /// The node returned `Ok`.
+    Ok(T),
+    /// The node returned `Err`.
+    Err(E),
+    /// The node panicked; holds the panic payload.
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The node never ran, because a node it depends on failed or
+    /// `shutdown_now` discarded it.
+    Cancelled,
+}
+
+impl<T, E> NodeResult<T, E> {
+    pub fn is_ok(&self) -> bool {
+        matches!(self, NodeResult::Ok(_))
+    }
+
+    /// The node's value, if it succeeded.
+    pub fn ok(self) -> Option<T> {
+        match self {
+            NodeResult::Ok(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for NodeResult<T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeResult::Ok(value) => f.debug_tuple("Ok").field(value).finish(),
+            NodeResult::Err(err) => f.debug_tuple("Err").field(err).finish(),
+            NodeResult::Panicked(_) => f.write_str("Panicked(..)"),
+            NodeResult::Cancelled => f.write_str("Cancelled"),
+        }
+    }
+}
+
+/// The result of every node of a graph, from `ThreadPool::run_graph`.
+/// Indexed by `NodeId`.
+#[derive(Debug)]
+pub struct GraphResults<T, E> {
+    results: Vec<NodeResult<T, E>>,
+}
+
+impl<T, E> GraphResults<T, E> {
+    /// Whether every node succeeded.
+    pub fn all_ok(&self) -> bool {
+        self.results.iter().all(NodeResult::is_ok)
+    }
+
+    /// The results in node order.
+    pub fn into_vec(self) -> Vec<NodeResult<T, E>> {
+        self.results
+    }
+}
+
+impl<T, E> Index<NodeId> for GraphResults<T, E> {
+    type Output = NodeResult<T, E>;
+
+    fn index(&self, node: NodeId) -> &NodeResult<T, E> {
+        &self.results[node.0]
+    }
+}
+
+impl ThreadPool {
+    /// Runs every node of `graph` on the pool, each once all of its
+    /// dependencies have succeeded, and waits for the whole graph.
+    ///
+    /// When a node fails, the nodes that depend on it are cancelled; the
+    /// rest of the graph still runs. A node the pool's queue won't take,
+    /// because it is full or the pool is shutting down, runs on the thread
+    /// that finished its last dependency, or on the caller's.
+    pub fn run_graph<T, E>(&self, graph: TaskGraph<T, E>) -> GraphResults<T, E>
+    where
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let mut jobs = Vec::with_capacity(graph.nodes.len());
+        let mut waiting = Vec::with_capacity(graph.nodes.len());
+        let mut dependents = Vec::with_capacity(graph.nodes.len());
+        for node in graph.nodes {
+            jobs.push(Some(node.run));
+            waiting.push(node.dependencies);
+            dependents.push(node.dependents);
+        }
+        let roots: Vec<usize> = (0..waiting.len()).filter(|&i| waiting[i] == 0).collect();
+        let run = Arc::new(GraphRun {
+            shared: Arc::downgrade(&self.shared),
+            dependents,
+            state: Mutex::new(RunState {
+                unresolved: jobs.len(),
+                results: jobs.iter().map(|_| None).collect(),
+                jobs,
+                waiting,
+            }),
+            done: Condvar::new(),
+        });
+        for node in roots {
+            run.start(node);
+        }
+        run.wait()
+    }
+}
+
+/// A graph being run by `ThreadPool::run_graph`.
+struct GraphRun<T, E> {
+    shared: Weak<Shared>,
+    dependents: Vec<Vec<usize>>,
+    state: Mutex<RunState<T, E>>,
+    done: Condvar,
+}
+
+struct RunState<T, E> {
+    jobs: Vec<Option<NodeFn<T, E>>>,
+    /// How many dependencies each node is still waiting for.
+    waiting: Vec<usize>,
+    results: Vec<Option<NodeResult<T, E>>>,
+    /// Nodes without a result yet.
+    unresolved: usize,
+}
+
+impl<T: Send + 'static, E: Send + 'static> GraphRun<T, E> {
+    fn start(self: &Arc<Self>, node: usize) {
+        let run = self.state.lock().unwrap().jobs[node].take();
+        let job = NodeJob {
+            graph: Arc::clone(self),
+            node,
+            run,
+        };
+        // Like a scoped job, a node the queue won't take runs on this thread.
+        if let Some(shared) = self.shared.upgrade() {
+            shared.submit_awaited(move || job.run());
+        }
+    }
+
+    /// Records a node's result, then starts the dependents it was the last
+    /// dependency of, or cancels all of its dependents if it failed.
+    fn finish(self: &Arc<Self>, node: usize, result: NodeResult<T, E>) {
+        let mut ready = Vec::new();
+        let mut state = self.state.lock().unwrap();
+        let failed = !result.is_ok();
+        state.results[node] = Some(result);
+        state.unresolved -= 1;
+        if failed {
+            let mut downstream = self.dependents[node].clone();
+            while let Some(dependent) = downstream.pop() {
+                if state.results[dependent].is_none() {
+                    state.results[dependent] = Some(NodeResult::Cancelled);
+                    state.jobs[dependent] = None;
+                    state.unresolved -= 1;
+                    downstream.extend_from_slice(&self.dependents[dependent]);
+                }
+            }
+        } else {
+            for &dependent in &self.dependents[node] {
+                state.waiting[dependent] -= 1;
+                if state.waiting[dependent] == 0 && state.results[dependent].is_none() {
+                    ready.push(dependent);
+                }
+            }
+        }
+        if state.unresolved == 0 {
+            self.done.notify_all();
+        }
+        drop(state);
+        for dependent in ready {
+            self.start(dependent);
+        }
+    }
+
+    fn wait(&self) -> GraphResults<T, E> {
+        worker::help_until(|| self.state.lock().unwrap().unresolved == 0);
+        let mut state = self.state.lock().unwrap();
+        while state.unresolved > 0 {
+            state = self.done.wait(state).unwrap();
+        }
+        let results = state.results.drain(..).map(Option::unwrap).collect();
+        GraphResults { results }
+    }
+}
+
+/// Runs one node. Dropping it without running, because `shutdown_now`
+/// discarded it, cancels the node so `run_graph` doesn't wait forever.
+struct NodeJob<T: Send + 'static, E: Send + 'static> {
+    graph: Arc<GraphRun<T, E>>,
+    node: usize,
+    run: Option<NodeFn<T, E>>,
+}
+
+impl<T: Send + 'static, E: Send + 'static> NodeJob<T, E> {
+    fn run(mut self) {
+        let Some(run) = self.run.take() else {
+            return;
+        };
+        let result = match panic::catch_unwind(AssertUnwindSafe(run)) {
+            Ok(Ok(value)) => NodeResult::Ok(value),
+            Ok(Err(err)) => NodeResult::Err(err),
+            Err(payload) => {
+                worker::report_caught_panic();
+                NodeResult::Panicked(payload)
+            }
+        };
+        self.graph.finish(self.node, result);
+    }
+}
+
+impl<T: Send + 'static, E: Send + 'static> Drop for NodeJob<T, E> {
+    fn drop(&mut self) {
+        if self.run.take().is_some() {
+            self.graph.finish(self.node, NodeResult::Cancelled);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackpressurePolicy, ThreadPoolBuilder};
+    use std::sync::Mutex;
+
+    #[test]
+    fn nodes_run_after_their_dependencies() {
+        let pool = ThreadPool::new(4);
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut graph = TaskGraphBuilder::<(), ()>::new();
+        let node = |name: &'static str| {
+            let log = Arc::clone(&log);
+            move || {
+                log.lock().unwrap().push(name);
+                Ok(())
+            }
+        };
+        let a = graph.add_node(node("a"));
+        let b = graph.add_node(node("b"));
+        let c = graph.add_node(node("c"));
+        let d = graph.add_node(node("d"));
+        graph.add_dependency(c, a);
+        graph.add_dependency(c, b);
+        graph.add_dependency(d, c);
+
+        let results = pool.run_graph(graph.build().unwrap());
+        assert!(results.all_ok());
+        let log = log.lock().unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(&log[2..], ["c", "d"]);
+    }
+
+    #[test]
+    fn failures_cancel_downstream_nodes() {
+        let pool = ThreadPool::new(2);
+        let mut graph = TaskGraphBuilder::<i32, &str>::new();
+        let a = graph.add_node(|| Err("a failed"));
+        let b = graph.add_node(|| Ok(2));
+        let c = graph.add_node(|| Ok(3));
+        let d = graph.add_node(|| Ok(4));
+        let e = graph.add_node(|| panic!("e failed"));
+        graph.add_dependency(c, a);
+        graph.add_dependency(c, b);
+        graph.add_dependency(d, c);
+
+        let results = pool.run_graph(graph.build().unwrap());
+        assert!(matches!(results[a], NodeResult::Err("a failed")));
+        assert!(matches!(results[b], NodeResult::Ok(2)));
+        assert!(matches!(results[c], NodeResult::Cancelled));
+        assert!(matches!(results[d], NodeResult::Cancelled));
+        assert!(matches!(results[e], NodeResult::Panicked(_)));
+    }
+
+    #[test]
+    fn a_full_queue_does_not_cancel_nodes() {
+        for policy in [BackpressurePolicy::Reject, BackpressurePolicy::DropOldest] {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(1)
+                .queue_capacity(1)
+                .backpressure(policy)
+                .build()
+                .unwrap();
+            let mut graph = TaskGraphBuilder::<usize, ()>::new();
+            for n in 0..5 {
+                graph.add_node(move || Ok(n));
+            }
+            let results = pool.run_graph(graph.build().unwrap()).into_vec();
+            let values: Vec<_> = results.into_iter().map(NodeResult::ok).collect();
+            assert_eq!(values, [0, 1, 2, 3, 4].map(Some), "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn cycles_are_rejected() {
+        let mut graph = TaskGraphBuilder::<(), ()>::new();
+        let a = graph.add_node(|| Ok(()));
+        let b = graph.add_node(|| Ok(()));
+        let c = graph.add_node(|| Ok(()));
+        graph.add_dependency(b, a);
+        graph.add_dependency(a, b);
+        graph.add_dependency(c, b);
+        match graph.build() {
+            Err(GraphError::Cycle(nodes)) => assert_eq!(nodes, [a, b, c]),
+            Ok(_) => panic!("cycle wasn't detected"),
+        }
+    }
+}
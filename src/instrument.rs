@@ -0,0 +1,282 @@
+//! Integration with the `log` and `tracing` crates, behind the cargo
+//! features of the same names.
+//!
+//! With `tracing`, every job runs inside a `job` span carrying the worker id,
+//! the job id and how long the job waited in the queue. With either feature,
+//! worker lifecycle events are emitted under the `multithreading` target.
+
+use std::time::Duration;
+
+#[cfg(any(feature = "log", feature = "tracing"))]
+use crate::PoolObserver;
+
+/// Keeps the current job's span entered until dropped.
+pub(crate) struct JobSpan {
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+/// Called by a worker right before it runs a job.
+#[cfg_attr(
+    not(any(feature = "log", feature = "tracing")),
+    allow(unused_variables)
+)]
+pub(crate) fn enter_job(worker: usize, job: u64, queue_wait: Duration) -> JobSpan {
+    #[cfg(feature = "log")]
+    log::trace!(
+        target: "multithreading",
+        "worker {} starting job {} after {:?} in the queue",
+        worker,
+        job,
+        queue_wait
+    );
+    JobSpan {
+        #[cfg(feature = "tracing")]
+        _span: tracing::debug_span!(
+            target: "multithreading",
+            "job",
+            worker,
+            job,
+            queue_wait_us = queue_wait.as_micros() as u64
+        )
+        .entered(),
+    }
+}
+
+#[cfg(feature = "log")]
+pub(crate) struct LogObserver;
+
+#[cfg(feature = "log")]
+impl PoolObserver for LogObserver {
+    fn on_worker_spawn(&self, worker: usize) {
+        log::debug!(target: "multithreading", "worker {} started", worker);
+    }
+
+    fn on_worker_exit(&self, worker: usize) {
+        log::debug!(target: "multithreading", "worker {} exited", worker);
+    }
+
+    fn on_job_end(&self, worker: usize, duration: Duration) {
+        log::trace!(
+            target: "multithreading",
+            "worker {} finished a job in {:?}",
+            worker,
+            duration
+        );
+    }
+
+    fn on_job_panic(&self, worker: usize) {
+        log::warn!(target: "multithreading", "job on worker {} panicked", worker);
+    }
+
+    fn on_job_timeout(&self, timeout: Duration) {
+        log::warn!(target: "multithreading", "job overran its {:?} timeout", timeout);
+    }
+
+    fn on_terminate(&self, worker: usize) {
+        log::debug!(target: "multithreading", "worker {} terminating", worker);
+    }
+
+    fn on_shutdown(&self) {
+        log::debug!(target: "multithreading", "pool shutting down");
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub(crate) struct TracingObserver;
+
+#[cfg(feature = "tracing")]
+impl PoolObserver for TracingObserver {
+    fn on_worker_spawn(&self, worker: usize) {
+        tracing::debug!(target: "multithreading", worker, "worker started");
+    }
+
+    fn on_worker_exit(&self, worker: usize) {
+        tracing::debug!(target: "multithreading", worker, "worker exited");
+    }
+
+    fn on_job_end(&self, worker: usize, duration: Duration) {
+        tracing::trace!(
+            target: "multithreading",
+            worker,
+            duration_us = duration.as_micros() as u64,
+            "job finished"
+        );
+    }
+
+    fn on_job_panic(&self, worker: usize) {
+        tracing::warn!(target: "multithreading", worker, "job panicked");
+    }
+
+    fn on_job_timeout(&self, timeout: Duration) {
+        tracing::warn!(
+            target: "multithreading",
+            timeout_us = timeout.as_micros() as u64,
+            "job timed out"
+        );
+    }
+
+    fn on_terminate(&self, worker: usize) {
+        tracing::debug!(target: "multithreading", worker, "worker terminating");
+    }
+
+    fn on_shutdown(&self) {
+        tracing::debug!(target: "multithreading", "pool shutting down");
+    }
+}
+
+#[cfg(all(test, any(feature = "log", feature = "tracing")))]
+mod tests {
+    use crate::ThreadPoolBuilder;
+    use std::sync::Mutex;
+    use std::thread;
+
+    /// What a capture saw, with the name of the thread that saw it, so that
+    /// each test only looks at the workers of its own uniquely named pool.
+    type Captured = Mutex<Vec<(String, String)>>;
+
+    fn thread_name() -> String {
+        thread::current().name().unwrap_or_default().to_owned()
+    }
+
+    #[cfg(feature = "log")]
+    struct LogCapture(Captured);
+
+    #[cfg(feature = "log")]
+    impl log::Log for LogCapture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == "multithreading"
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                let message = record.args().to_string();
+                self.0.lock().unwrap().push((thread_name(), message));
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(feature = "log")]
+    static LOGS: LogCapture = LogCapture(Mutex::new(Vec::new()));
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn jobs_and_lifecycle_are_logged() {
+        log::set_logger(&LOGS).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let pool = ThreadPoolBuilder::new()
+            .name("logged")
+            .num_threads(1)
+            .build()
+            .unwrap();
+        pool.execute(|| {});
+        drop(pool);
+
+        let messages = LOGS.0.lock().unwrap();
+        let has = |needle: &str| {
+            messages
+                .iter()
+                .any(|(thread, m)| thread == "logged-worker-0" && m.contains(needle))
+        };
+        assert!(has("worker 0 started"));
+        assert!(has("worker 0 starting job 0 after"));
+        assert!(has("worker 0 exited"));
+    }
+
+    /// Records, for each event, the fields of the spans it was inside.
+    #[cfg(feature = "tracing")]
+    struct SpanCapture {
+        spans: Mutex<Vec<String>>,
+        events: Captured,
+    }
+
+    #[cfg(feature = "tracing")]
+    thread_local! {
+        static ENTERED: std::cell::RefCell<Vec<u64>> = const { std::cell::RefCell::new(Vec::new()) };
+    }
+
+    /// Writes fields out as `name=value` pairs.
+    #[cfg(feature = "tracing")]
+    struct Fields<'a>(&'a mut String);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::field::Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for SpanCapture {
+        fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+            metadata.target() == "multithreading" || metadata.target() == module_path!()
+        }
+
+        fn new_span(&self, attrs: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut span = attrs.metadata().name().to_owned();
+            attrs.record(&mut Fields(&mut span));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(span);
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let spans = self.spans.lock().unwrap();
+            let mut message = ENTERED.with(|entered| {
+                let entered = entered.borrow();
+                let names = entered.iter().map(|&id| spans[id as usize - 1].as_str());
+                names.collect::<Vec<_>>().join(" > ")
+            });
+            message.push_str(" :");
+            event.record(&mut Fields(&mut message));
+            self.events.lock().unwrap().push((thread_name(), message));
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+        }
+
+        fn exit(&self, _span: &tracing::span::Id) {
+            ENTERED.with(|entered| entered.borrow_mut().pop());
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn jobs_run_inside_a_span() {
+        let capture = std::sync::Arc::new(SpanCapture {
+            spans: Mutex::new(Vec::new()),
+            events: Mutex::new(Vec::new()),
+        });
+        let dispatch = tracing::Dispatch::from(std::sync::Arc::clone(&capture));
+        tracing::dispatcher::set_global_default(dispatch).unwrap();
+
+        let pool = ThreadPoolBuilder::new()
+            .name("traced")
+            .num_threads(1)
+            .build()
+            .unwrap();
+        pool.execute(|| tracing::info!("inside"));
+        drop(pool);
+
+        let events = capture.events.lock().unwrap();
+        let (_, inside) = events
+            .iter()
+            .find(|(thread, event)| {
+                thread == "traced-worker-0" && event.ends_with("message=inside")
+            })
+            .expect("the job's event was captured");
+        assert!(
+            inside.starts_with("job worker=0 job=0 queue_wait_us="),
+            "{}",
+            inside
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use crate::ThreadPool;
+
+impl ThreadPool {
+    /// Applies `f` to every item in parallel and returns the results in input order.
+    ///
+    /// A panic in `f` is resumed on the calling thread once every chunk has
+    /// finished.
+    ///
+    /// ```
+    /// use multithreading::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let squares = pool.map(1..=5, |n| n * n);
+    /// assert_eq!(squares, [1, 4, 9, 16, 25]);
+    /// ```
+    pub fn map<I, F, T>(&self, iter: I, f: F) -> Vec<T>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> T + Sync,
+        T: Send,
+    {
+        let chunks = self.run_chunks(iter, |chunk| chunk.into_iter().map(&f).collect::<Vec<_>>());
+        chunks.into_iter().flatten().collect()
+    }
+
+    /// Calls `f` on every element of `slice` in parallel.
+    pub fn for_each<T, F>(&self, slice: &[T], f: F)
+    where
+        T: Sync,
+        F: Fn(&T) + Sync,
+    {
+        self.run_chunks(slice, |chunk| chunk.into_iter().for_each(&f));
+    }
+
+    /// Like `map`, but only keeps the items for which `f` returns `Some`.
+    pub fn filter_map<I, F, T>(&self, iter: I, f: F) -> Vec<T>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(I::Item) -> Option<T> + Sync,
+        T: Send,
+    {
+        let chunks = self.run_chunks(iter, |chunk| {
+            chunk.into_iter().filter_map(&f).collect::<Vec<_>>()
+        });
+        chunks.into_iter().flatten().collect()
+    }
+
+    /// Folds every item with `op`, starting each chunk from `identity()`.
+    ///
+    /// `op` must be associative and `identity()` must be its neutral element,
+    /// since items are combined in chunks whose results are then combined in
+    /// input order.
+    ///
+    /// ```
+    /// use multithreading::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let sum = pool.reduce(1..=100, || 0, |a, b| a + b);
+    /// assert_eq!(sum, 5050);
+    /// ```
+    pub fn reduce<I, ID, OP, T>(&self, iter: I, identity: ID, op: OP) -> T
+    where
+        I: IntoIterator<Item = T>,
+        T: Send,
+        ID: Fn() -> T + Sync,
+        OP: Fn(T, T) -> T + Sync,
+    {
+        let chunks = self.run_chunks(iter, |chunk| chunk.into_iter().fold(identity(), &op));
+        chunks.into_iter().fold(identity(), &op)
+    }
+
+    /// Splits the items into roughly one chunk per worker, runs `f` on each
+    /// chunk in a scope and returns the chunk results in input order.
+    fn run_chunks<I, F, R>(&self, iter: I, f: F) -> Vec<R>
+    where
+        I: IntoIterator,
+        I::Item: Send,
+        F: Fn(Vec<I::Item>) -> R + Sync,
+        R: Send,
+    {
+        let items: Vec<I::Item> = iter.into_iter().collect();
+        if items.is_empty() {
+            return Vec::new();
+        }
+        let chunk_size = items.len().div_ceil(self.num_threads().max(1));
+
+        let mut chunks = Vec::new();
+        let mut items = items.into_iter().peekable();
+        while items.peek().is_some() {
+            chunks.push(items.by_ref().take(chunk_size).collect::<Vec<_>>());
+        }
+
+        let mut results: Vec<Option<R>> = chunks.iter().map(|_| None).collect();
+        self.scope(|s| {
+            for (chunk, slot) in chunks.into_iter().zip(results.iter_mut()) {
+                let f = &f;
+                s.spawn(move || *slot = Some(f(chunk)));
+            }
+        });
+        results
+            .into_iter()
+            .map(|result| result.expect("scope returned before a chunk finished"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn map_preserves_order() {
+        let pool = ThreadPool::new(3);
+        let input: Vec<usize> = (0..100).collect();
+        let doubled = pool.map(input.iter(), |n| n * 2);
+        assert_eq!(doubled, input.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn filter_map_and_for_each() {
+        let pool = ThreadPool::new(3);
+        let evens = pool.filter_map(0..10, |n| (n % 2 == 0).then_some(n));
+        assert_eq!(evens, [0, 2, 4, 6, 8]);
+
+        let seen = AtomicUsize::new(0);
+        pool.for_each(&evens, |n| {
+            seen.fetch_add(*n, Ordering::SeqCst);
+        });
+        assert_eq!(seen.into_inner(), 20);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad item")]
+    fn panics_propagate() {
+        let pool = ThreadPool::new(2);
+        pool.map(0..10, |n| {
+            if n == 7 {
+                panic!("bad item");
+            }
+            n
+        });
+    }
+}
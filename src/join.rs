@@ -0,0 +1,156 @@
+//! Fork-join parallelism for divide-and-conquer algorithms.
+
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+
+use crate::task::{self, Completer};
+use crate::{TaskError, ThreadPool};
+
+/// The forked half of a `fork_join`, queued as a job.
+struct ForkedJob<F, T> {
+    f: Option<F>,
+    completer: Option<Completer<T>>,
+}
+
+impl<F: FnOnce() -> T, T> ForkedJob<F, T> {
+    fn run(mut self) {
+        let f = self.f.take().unwrap();
+        self.completer.take().unwrap().run(|_| f());
+    }
+}
+
+impl<F, T> Drop for ForkedJob<F, T> {
+    /// Drops the closure before the handle is resolved, so it can't outlive
+    /// the `fork_join` call it borrows from.
+    fn drop(&mut self) {
+        drop(self.f.take());
+        drop(self.completer.take());
+    }
+}
+
+impl ThreadPool {
+    /// Runs `a` and `b`, potentially in parallel, and returns both results.
+    ///
+    /// `b` is queued on the pool while the calling thread runs `a`. Called
+    /// from one of the pool's own workers, the caller then runs other queued
+    /// jobs until `b` is done instead of blocking, so recursive calls can't
+    /// run out of workers. Both closures may borrow from the caller's stack.
+    ///
+    /// ```
+    /// use multithreading::ThreadPool;
+    ///
+    /// fn sum(pool: &ThreadPool, numbers: &[u64]) -> u64 {
+    ///     if numbers.len() <= 1024 {
+    ///         return numbers.iter().sum();
+    ///     }
+    ///     let (left, right) = numbers.split_at(numbers.len() / 2);
+    ///     let (a, b) = pool.fork_join(|| sum(pool, left), || sum(pool, right));
+    ///     a + b
+    /// }
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let numbers: Vec<u64> = (1..=100_000).collect();
+    /// assert_eq!(sum(&pool, &numbers), 5_000_050_000);
+    /// ```
+    ///
+    /// If the pool no longer accepts jobs, or its queue is full and rejects
+    /// them, `b` runs on the calling thread before `a`.
+    ///
+    /// # Panics
+    ///
+    /// If either closure panics, once both have finished. The panic from
+    /// `a` is resumed if both did. Also panics if the pool dropped `b`
+    /// without running it, which only `shutdown_now` does.
+    pub fn fork_join<A, B, RA, RB>(&self, a: A, b: B) -> (RA, RB)
+    where
+        A: FnOnce() -> RA + Send,
+        B: FnOnce() -> RB + Send,
+        RA: Send,
+        RB: Send,
+    {
+        let (completer, handle) = task::task();
+        let forked = ForkedJob {
+            f: Some(b),
+            completer: Some(completer),
+        };
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || forked.run());
+        // SAFETY: the job is either run or dropped before its handle is
+        // resolved, and this function doesn't return until it is.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+        self.shared.submit_awaited(job);
+
+        let result_a = panic::catch_unwind(AssertUnwindSafe(a));
+        // On a worker, this runs other jobs until `b` is done.
+        let result_b = handle.join();
+
+        let result_a = match result_a {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        };
+        match result_b {
+            Ok(value) => (result_a, value),
+            Err(TaskError::Panicked(payload)) => panic::resume_unwind(payload),
+            Err(err) => panic!("fork_join task failed: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadPool;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn fib(pool: &ThreadPool, n: u64) -> u64 {
+        if n < 2 {
+            return n;
+        }
+        let (a, b) = pool.fork_join(|| fib(pool, n - 1), || fib(pool, n - 2));
+        a + b
+    }
+
+    #[test]
+    fn recursion_on_the_workers_does_not_deadlock() {
+        let pool = ThreadPool::new(2);
+        let mut on_worker = 0;
+        pool.scope(|s| s.spawn(|| on_worker = fib(&pool, 16)));
+        assert_eq!(on_worker, 987);
+        // From outside the pool, too.
+        assert_eq!(fib(&pool, 12), 144);
+    }
+
+    #[test]
+    fn panics_are_resumed_after_both_halves_finish() {
+        let pool = ThreadPool::new(2);
+        let mut finished = false;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.fork_join(|| panic!("left"), || finished = true)
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"left"));
+        assert!(finished);
+    }
+
+    #[test]
+    fn shutdown_now_does_not_hang_on_recursion() {
+        let mut pool = ThreadPool::new(4);
+        for _ in 0..4 {
+            pool.execute(|| {
+                let pool = ThreadPool::current().unwrap();
+                fib(&pool, 25);
+            });
+        }
+        thread::sleep(Duration::from_millis(20));
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // Dropping the jobs it returns lets the workers waiting on them
+            // finish.
+            drop(pool.shutdown_now());
+            let _ = pool.join();
+            tx.send(()).unwrap();
+        });
+        rx.recv_timeout(Duration::from_secs(10))
+            .expect("shutdown_now hung");
+    }
+}
@@ -1,145 +1,993 @@
-use std::sync::{mpsc, Arc, Mutex};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "affinity")]
+mod affinity;
+mod backpressure;
+mod builder;
+mod cancel;
+mod future;
+mod graph;
+mod instrument;
+mod iter;
+mod join;
+mod metrics;
+mod observer;
+#[cfg(all(
+    target_os = "linux",
+    any(feature = "affinity", feature = "thread-priority")
+))]
+mod os;
+mod priority;
+mod queue;
+mod scope;
+mod sync;
+mod task;
+#[cfg(feature = "thread-priority")]
+mod thread_priority;
+mod timer;
+mod worker;
+
+#[cfg(feature = "affinity")]
+pub use affinity::WorkerAffinity;
+pub use backpressure::{BackpressurePolicy, BackpressureStats};
+pub use builder::{BuildError, ThreadPoolBuilder};
+pub use cancel::CancellationToken;
+pub use graph::{GraphError, GraphResults, NodeId, NodeResult, TaskGraph, TaskGraphBuilder};
+pub use metrics::{Histogram, PoolMetrics, WorkerMetrics};
+pub use observer::PoolObserver;
+pub use priority::Priority;
+pub use scope::Scope;
+pub use task::{TaskError, TaskHandle};
+#[cfg(feature = "thread-priority")]
+pub use thread_priority::SchedPolicy;
+pub use timer::{ScheduleError, ScheduleHandle};
+
+use crossbeam_deque::Worker as Deque;
+use queue::Queue;
+use sync::WaitCounter;
+use task::Deadline;
+use timer::{Period, Task, Timer};
+use worker::Worker;
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::Sender<Message>,
-    debug: bool,
-    is_running: bool,
+    shared: Arc<Shared>,
+    /// False for handles from `ThreadPool::current`, which don't shut the
+    /// pool down when dropped.
+    owned: bool,
+}
+
+/// Where a pool is in its lifecycle. States only ever move forward.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PoolState {
+    /// Accepting and running jobs.
+    Running,
+    /// No longer accepting jobs; workers finish everything already queued.
+    ShuttingDown,
+    /// No longer accepting jobs; workers exit after their current job.
+    Stopping,
+    /// Every worker has exited.
+    Terminated,
 }
 
-enum Message {
-    NewJob(Job),
-    Terminate,
+impl PoolState {
+    fn from_u8(state: u8) -> PoolState {
+        match state {
+            0 => PoolState::Running,
+            1 => PoolState::ShuttingDown,
+            2 => PoolState::Stopping,
+            _ => PoolState::Terminated,
+        }
+    }
+}
+
+/// State shared between the pool handle and its workers.
+pub(crate) struct Shared {
+    queue: Queue,
+    state: AtomicU8,
+    /// Submissions from outside the pool that have seen `Running` but may
+    /// not have pushed their job yet. Draining workers wait for it to reach
+    /// zero so no accepted job is left behind.
+    submitting: AtomicUsize,
+    panicked: AtomicUsize,
+    next_job_id: AtomicU64,
+    workers: Mutex<Vec<Worker>>,
+    next_worker_id: AtomicUsize,
+    live_workers: WaitCounter,
+    /// Live workers, less those parked inside a job that is waiting for
+    /// another job (see `worker::help_until`). `shutdown_now` waits for it
+    /// to reach zero rather than for the workers to exit.
+    working: WaitCounter,
+    /// Workers that are kept even when idle.
+    core_workers: AtomicUsize,
+    /// The most workers the pool may have. Equal to `core_workers` unless
+    /// the pool is elastic. Workers beyond it retire between jobs.
+    max_workers: AtomicUsize,
+    /// How long a worker beyond `core_workers` may sit idle, if the pool is
+    /// elastic.
+    keep_alive: Option<Duration>,
+    /// Held while changing the worker limits or retiring a worker, so the
+    /// two never race and leave the pool with too few workers.
+    resize_lock: Mutex<()>,
+    /// Jobs that have been queued but haven't finished running yet.
+    unfinished: WaitCounter,
+    queue_capacity: Option<usize>,
+    backpressure: BackpressurePolicy,
+    backpressure_counters: backpressure::Counters,
+    observers: Vec<Arc<dyn PoolObserver>>,
+    metrics: metrics::Recorder,
+    /// Configuration for workers spawned after the pool was built.
+    builder: ThreadPoolBuilder,
+    /// Started on first use while the pool is running, and stopped once its
+    /// workers have been joined, so deadlines are enforced during a drain.
+    timer: Mutex<Option<Arc<Timer>>>,
+}
+
+impl Shared {
+    fn new(builder: ThreadPoolBuilder) -> Shared {
+        let elastic = builder.elastic();
+        Shared {
+            queue: Queue::new(builder.priority_aging),
+            state: AtomicU8::new(PoolState::Running as u8),
+            submitting: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            next_job_id: AtomicU64::new(0),
+            workers: Mutex::new(Vec::new()),
+            next_worker_id: AtomicUsize::new(0),
+            live_workers: WaitCounter::new(),
+            working: WaitCounter::new(),
+            core_workers: AtomicUsize::new(0),
+            max_workers: AtomicUsize::new(elastic.map_or(0, |(max, _)| max)),
+            keep_alive: elastic.map(|(_, keep_alive)| keep_alive),
+            resize_lock: Mutex::new(()),
+            unfinished: WaitCounter::new(),
+            queue_capacity: builder.queue_capacity,
+            backpressure: builder.backpressure,
+            backpressure_counters: backpressure::Counters::default(),
+            observers: builder.observers(),
+            metrics: metrics::Recorder::new(),
+            builder,
+            timer: Mutex::new(None),
+        }
+    }
+
+    fn state(&self) -> PoolState {
+        PoolState::from_u8(self.state.load(Ordering::SeqCst))
+    }
+
+    fn observe(&self, event: impl Fn(&dyn PoolObserver)) {
+        for observer in &self.observers {
+            event(observer.as_ref());
+        }
+    }
+
+    /// Moves the pool to a shutdown `state`, telling the observers if it
+    /// was still running.
+    fn begin_shutdown(&self, state: PoolState) {
+        if self.advance(state) == PoolState::Running {
+            self.observe(|observer| observer.on_shutdown());
+        }
+    }
+
+    /// Moves the pool forward to `state`, unless it's already there or
+    /// beyond. Returns the previous state.
+    fn advance(&self, state: PoolState) -> PoolState {
+        let previous = self.state.fetch_max(state as u8, Ordering::SeqCst);
+        self.queue.wake_all();
+        PoolState::from_u8(previous)
+    }
+
+    fn has_surplus_workers(&self) -> bool {
+        self.live_workers.get() > self.max_workers.load(Ordering::SeqCst)
+    }
+
+    /// Called by a worker between jobs, or once it has been idle for
+    /// `keep_alive`. Returns true if the pool has more workers than it
+    /// should, in which case the caller has been removed from
+    /// `live_workers` and must exit.
+    fn claim_retirement(&self, idle: bool) -> bool {
+        let limit = if idle {
+            &self.core_workers
+        } else {
+            &self.max_workers
+        };
+        let surplus = || self.live_workers.get() > limit.load(Ordering::SeqCst);
+        if !surplus() {
+            return false;
+        }
+        let _guard = self.resize_lock.lock().unwrap();
+        if !surplus() {
+            return false;
+        }
+        self.live_workers.sub(1);
+        true
+    }
+
+    /// Starts one more worker. Must be called with `resize_lock` held.
+    fn spawn_worker(self: &Arc<Self>, workers: &mut Vec<Worker>) -> Result<(), BuildError> {
+        let id = self.next_worker_id.fetch_add(1, Ordering::Relaxed);
+        let worker = Worker::new(id, self.builder.thread_builder(id), Arc::clone(self))?;
+        workers.push(worker);
+        Ok(())
+    }
+
+    /// Spawns an extra worker if the pool is elastic and has more jobs
+    /// queued than idle workers to take them.
+    ///
+    /// Growing is best effort: it's skipped if another thread is resizing
+    /// or shutting the pool down.
+    fn grow_if_backed_up(self: &Arc<Self>) {
+        let live = self.live_workers.get();
+        if live >= self.max_workers.load(Ordering::SeqCst) {
+            return;
+        }
+        let idle = live.saturating_sub(self.metrics.active());
+        if self.queue.len() <= idle {
+            return;
+        }
+        let Ok(mut workers) = self.workers.try_lock() else {
+            return;
+        };
+        let _guard = self.resize_lock.lock().unwrap();
+        if self.state() == PoolState::Running
+            && self.live_workers.get() < self.max_workers.load(Ordering::SeqCst)
+        {
+            reap_retired(&mut workers);
+            let _ = self.spawn_worker(&mut workers);
+        }
+    }
+
+    /// Queues a job, or gives it back if the pool no longer accepts jobs.
+    ///
+    /// Jobs submitted from one of this pool's workers are still accepted
+    /// during a graceful shutdown, since they're part of work already queued.
+    fn submit<F>(self: &Arc<Self>, f: F, priority: Priority) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit_job(f, priority, Admission::Detached)
+    }
+
+    /// Queues a job whose caller blocks until it has run, such as a scoped
+    /// or forked job. If the queue won't take it, whether because it is full
+    /// or because the pool is shutting down, it runs on the calling thread
+    /// instead, and isn't counted as rejected.
+    fn submit_awaited<F>(self: &Arc<Self>, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(err) = self.submit_job(f, Priority::Normal, Admission::Awaited) {
+            self.run_inline(self.job(err.into_inner(), Priority::Normal, Admission::Awaited));
+        }
+    }
+
+    /// Queues a job that carries on work the pool has already accepted,
+    /// like a scheduled job that has come due. The queue takes it even when
+    /// full, so this never blocks and backpressure never discards it; it
+    /// only fails once the pool stops accepting jobs.
+    fn submit_resumed<F>(self: &Arc<Self>, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit_job(f, Priority::Normal, Admission::Resumed)
+    }
+
+    fn submit_job<F>(
+        self: &Arc<Self>,
+        f: F,
+        priority: Priority,
+        admission: Admission,
+    ) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        worker::with_local(self, |local| {
+            let result = if local.is_some() {
+                self.enqueue(f, priority, admission, local)
+            } else {
+                self.submitting.fetch_add(1, Ordering::SeqCst);
+                let result = self.enqueue(f, priority, admission, None);
+                self.submitting.fetch_sub(1, Ordering::SeqCst);
+                result
+            };
+            match result {
+                Ok(()) => self.grow_if_backed_up(),
+                Err(_) if admission == Admission::Detached => self.metrics.rejected(),
+                Err(_) => {}
+            }
+            result
+        })
+    }
+
+    fn enqueue<F>(
+        &self,
+        f: F,
+        priority: Priority,
+        admission: Admission,
+        local: Option<&Deque<Job>>,
+    ) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let accepting = |state| match local {
+            Some(_) => state <= PoolState::ShuttingDown,
+            None => state == PoolState::Running,
+        };
+        if !accepting(self.state()) {
+            return Err(ExecuteError::Shutdown(f));
+        }
+        if local.is_none() && self.live_workers.get() == 0 {
+            return Err(ExecuteError::NoWorkers(f));
+        }
+
+        let capacity = match admission {
+            Admission::Resumed => None,
+            Admission::Detached | Admission::Awaited => self.queue_capacity,
+        };
+        if !self.queue.reserve(capacity) {
+            let capacity = self.queue_capacity.unwrap_or(usize::MAX);
+            match self.backpressure {
+                BackpressurePolicy::Block if local.is_none() => {
+                    self.backpressure_counters.record(BackpressurePolicy::Block);
+                    if !self
+                        .queue
+                        .reserve_blocking(capacity, || accepting(self.state()))
+                    {
+                        return Err(ExecuteError::Shutdown(f));
+                    }
+                }
+                BackpressurePolicy::Reject if admission == Admission::Detached => {
+                    self.backpressure_counters
+                        .record(BackpressurePolicy::Reject);
+                    return Err(ExecuteError::Full(f));
+                }
+                BackpressurePolicy::Block
+                | BackpressurePolicy::Reject
+                | BackpressurePolicy::CallerRuns => {
+                    self.backpressure_counters
+                        .record(BackpressurePolicy::CallerRuns);
+                    self.run_inline(self.job(f, priority, admission));
+                    return Ok(());
+                }
+                BackpressurePolicy::DropOldest => {
+                    while !self.queue.reserve(Some(capacity)) {
+                        if let Some(oldest) = self.queue.pop_oldest() {
+                            if oldest.evictable {
+                                self.backpressure_counters
+                                    .record(BackpressurePolicy::DropOldest);
+                                drop(oldest);
+                            } else {
+                                self.backpressure_counters
+                                    .record(BackpressurePolicy::CallerRuns);
+                                self.run_inline(oldest);
+                            }
+                            self.unfinished.sub(1);
+                        } else {
+                            thread::yield_now();
+                        }
+                    }
+                }
+            }
+        }
+        self.unfinished.add(1);
+        self.queue.push(self.job(f, priority, admission), local);
+        Ok(())
+    }
+
+    /// The timer, started if the pool is still running. Fails with
+    /// `ScheduleError::Shutdown` once the pool has left `Running` without
+    /// one, or the timer was stopped.
+    ///
+    /// The state is checked with the lock held, and `stop_timer` takes the
+    /// lock after the state has moved on, so no timer can be started that
+    /// `stop_timer` misses.
+    fn timer(self: &Arc<Self>) -> Result<Arc<Timer>, ScheduleError> {
+        let mut timer = self.timer.lock().unwrap();
+        if timer.is_none() && self.state() == PoolState::Running {
+            let started = Timer::start(Arc::downgrade(self), self.builder.pool_name())
+                .map_err(ScheduleError::Spawn)?;
+            *timer = Some(started);
+        }
+        timer.clone().ok_or(ScheduleError::Shutdown)
+    }
+
+    /// Stops the timer thread, dropping the jobs it still holds, and waits
+    /// for it to exit.
+    fn stop_timer(&self) {
+        let timer = self.timer.lock().unwrap().take();
+        if let Some(timer) = timer {
+            timer.stop();
+            timer.join();
+        }
+    }
+
+    fn schedule(
+        self: &Arc<Self>,
+        due: Instant,
+        task: Task,
+    ) -> Result<ScheduleHandle, ScheduleError> {
+        let timer = self.timer()?;
+        if self.state() != PoolState::Running {
+            return Err(ScheduleError::Shutdown);
+        }
+        Ok(timer.schedule(due, task))
+    }
+
+    /// Times out a task that has just started, unless it finishes within
+    /// `timeout`. Cancel the returned handle once it does.
+    ///
+    /// Returns `None`, leaving the task to run as long as it likes, if the
+    /// pool has no timer left.
+    fn expire_after<T>(
+        self: &Arc<Self>,
+        timeout: Duration,
+        deadline: Deadline<T>,
+    ) -> Option<ScheduleHandle>
+    where
+        T: Send + 'static,
+    {
+        let shared = Arc::downgrade(self);
+        let expire = move || {
+            if deadline.expire() {
+                if let Some(shared) = shared.upgrade() {
+                    shared.metrics.timed_out();
+                    shared.observe(|observer| observer.on_job_timeout(timeout));
+                }
+            }
+        };
+        let due = Instant::now() + timeout;
+        let timer = self.timer().ok()?;
+        Some(timer.schedule(due, Task::OnTimer(Box::new(expire))))
+    }
+
+    /// Takes every job that hasn't started off the queue.
+    fn drain(&self) -> Vec<Job> {
+        let jobs = self.queue.drain();
+        self.unfinished.sub(jobs.len());
+        jobs
+    }
+
+    fn job<F>(&self, f: F, priority: Priority, admission: Admission) -> Job
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        Job {
+            id: self.next_job_id.fetch_add(1, Ordering::Relaxed),
+            queued_at: Instant::now(),
+            priority,
+            evictable: admission == Admission::Detached,
+            run: Box::new(f),
+        }
+    }
+
+    /// Runs a job on the calling thread, counting it like a worker would.
+    fn run_inline(&self, job: Job) {
+        let start = Instant::now();
+        let outcome = worker::run_job(job.run);
+        self.metrics.ran_inline(start.elapsed());
+        self.record_outcome(outcome);
+    }
+
+    /// Counts a finished job. Only panics that escaped the job count towards
+    /// `panicked_jobs`; the others were delivered to the task's caller.
+    fn record_outcome(&self, outcome: worker::Outcome) {
+        match outcome {
+            worker::Outcome::Completed => self.metrics.completed(),
+            worker::Outcome::Caught => self.metrics.panicked(),
+            worker::Outcome::Panicked => {
+                self.panicked.fetch_add(1, Ordering::SeqCst);
+                self.metrics.panicked();
+            }
+        }
+    }
 }
 
 impl ThreadPool {
     pub fn new(size: usize) -> ThreadPool {
         assert!(size > 0);
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver), false));
-        }
-        ThreadPool {
-            workers,
-            sender,
-            debug: false,
-            is_running: true,
-        }
+        ThreadPoolBuilder::new()
+            .num_threads(size)
+            .build()
+            .expect("failed to spawn worker threads")
     }
 
     pub fn new_with_debug(size: usize) -> ThreadPool {
         assert!(size > 0);
 
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver), true));
+        ThreadPoolBuilder::new()
+            .num_threads(size)
+            .debug(true)
+            .build()
+            .expect("failed to spawn worker threads")
+    }
+
+    /// The pool whose job is running on the current thread, or `None` if
+    /// this isn't a worker thread.
+    ///
+    /// The handle doesn't own the pool: dropping it leaves the pool running.
+    /// Waiting on the pool's work from a job through it is safe, since
+    /// `TaskHandle::join` and `scope` run other queued jobs on the waiting
+    /// worker. Shutting the pool down from one of its own jobs is not, and
+    /// deadlocks.
+    ///
+    /// ```
+    /// use multithreading::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(1);
+    /// let handle = pool.submit(|| {
+    ///     let pool = ThreadPool::current().unwrap();
+    ///     // The only worker runs this job while it waits for it.
+    ///     pool.submit(|| 6 * 7).join().unwrap()
+    /// });
+    /// assert_eq!(handle.join().unwrap(), 42);
+    /// assert!(ThreadPool::current().is_none());
+    /// ```
+    pub fn current() -> Option<ThreadPool> {
+        worker::current().map(|shared| ThreadPool {
+            shared,
+            owned: false,
+        })
+    }
+    /// Runs `f` on the pool.
+    ///
+    /// This is `try_execute` followed by a panic if the job is rejected.
+    ///
+    /// # Panics
+    ///
+    /// If `try_execute` would return an error, for example because the pool
+    /// has been shut down.
+    pub fn execute<F>(&self, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(err) = self.try_execute(f) {
+            panic!("ThreadPool::execute() failed: {}", err);
         }
-        ThreadPool {
-            workers,
-            sender,
-            debug: true,
-            is_running: true,
+    }
+    /// Runs `f` on the pool, or hands it back if the pool can't accept it.
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared.submit(f, Priority::Normal)
+    }
+    /// Like `execute`, but workers take queued jobs of a higher `priority`
+    /// first.
+    ///
+    /// # Panics
+    ///
+    /// If the job is rejected, like `execute`.
+    pub fn execute_with_priority<F>(&self, priority: Priority, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        if let Err(err) = self.shared.submit(f, priority) {
+            panic!("ThreadPool::execute_with_priority() failed: {}", err);
         }
     }
-    pub fn execute<F>(&self, f: F)
+    pub fn submit<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit_cancellable(move |_| f())
+    }
+    /// Like `submit`, but `f` gets the task's `CancellationToken` so it can
+    /// stop early once `TaskHandle::cancel` is called.
+    pub fn submit_cancellable<F, T>(&self, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (completer, handle) = task::task();
+        self.execute(move || completer.run(f));
+        handle
+    }
+    /// Like `submit_cancellable`, but gives up on the job if it runs for
+    /// longer than `timeout`.
+    ///
+    /// The timeout counts from when a worker starts the job. Once it
+    /// passes, the job's token is cancelled, the overrun is counted in
+    /// `PoolMetrics::timed_out` and reported to `PoolObserver::on_job_timeout`,
+    /// and the handle resolves with `TaskError::TimedOut`. The worker stays
+    /// busy until the job notices the token and returns.
+    ///
+    /// # Panics
+    ///
+    /// Like `execute`, and also if the timer thread can't be spawned.
+    pub fn execute_with_timeout<F, T>(&self, timeout: Duration, f: F) -> TaskHandle<T>
+    where
+        F: FnOnce(&CancellationToken) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (completer, handle) = task::task();
+        // Start the timer now, while the pool is running, so the deadline is
+        // enforced even if the job only starts during a graceful shutdown.
+        if let Err(ScheduleError::Spawn(err)) = self.shared.timer() {
+            panic!("failed to spawn the timer thread: {}", err);
+        }
+        let shared = Arc::downgrade(&self.shared);
+        self.execute(move || {
+            let expiry = shared
+                .upgrade()
+                .and_then(|shared| shared.expire_after(timeout, completer.deadline()));
+            completer.run(f);
+            if let Some(expiry) = expiry {
+                expiry.cancel();
+            }
+        });
+        handle
+    }
+    /// Runs `f` on the pool once `delay` has passed.
+    ///
+    /// Scheduled jobs are held by a timer thread, started on first use, and
+    /// queued like any other job when due. Jobs that aren't due yet when the
+    /// pool shuts down never run.
+    ///
+    /// Fails with `ScheduleError::Shutdown` once the pool has started
+    /// shutting down, or with `ScheduleError::Spawn` if the timer thread
+    /// can't be started.
+    pub fn schedule_after<F>(&self, delay: Duration, f: F) -> Result<ScheduleHandle, ScheduleError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let task = Task::Once(Box::new(f));
+        self.shared.schedule(Instant::now() + delay, task)
+    }
+    /// Runs `f` on the pool at `deadline`, or right away if it has passed.
+    /// Fails like `schedule_after`.
+    pub fn schedule_at<F>(&self, deadline: Instant, f: F) -> Result<ScheduleHandle, ScheduleError>
     where
         F: FnOnce() + Send + 'static,
     {
-        if !self.is_running {
-            panic!("ThreadPool shutted down.\nUsed ThreadPool::join() and then ThreadPool::execute().\nThis can not be done.");
+        let task = Task::Once(Box::new(f));
+        self.shared.schedule(deadline, task)
+    }
+    /// Runs `f` on the pool every `period`, starting one period from now,
+    /// until the handle is cancelled.
+    ///
+    /// Runs are at a fixed rate: each is due one period after the previous
+    /// one was due, however long it took. A run that is late because the
+    /// previous one overran starts as soon as that one finishes; runs never
+    /// overlap. If `f` panics, it isn't run again.
+    ///
+    /// Fails like `schedule_after`.
+    pub fn schedule_every<F>(&self, period: Duration, f: F) -> Result<ScheduleHandle, ScheduleError>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let task = Task::Repeat(Box::new(f), Period::FixedRate(period));
+        self.shared.schedule(Instant::now() + period, task)
+    }
+    /// Like `schedule_every`, but each run is due `delay` after the previous
+    /// one finished.
+    pub fn schedule_with_fixed_delay<F>(
+        &self,
+        delay: Duration,
+        f: F,
+    ) -> Result<ScheduleHandle, ScheduleError>
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let task = Task::Repeat(Box::new(f), Period::FixedDelay(delay));
+        self.shared.schedule(Instant::now() + delay, task)
+    }
+    /// Waits for every queued job to finish and shuts the workers down.
+    ///
+    /// Returns an error if any job passed to `execute` panicked during the
+    /// lifetime of the pool. Panics in jobs passed to `submit` are delivered
+    /// through their `TaskHandle` instead.
+    pub fn join(&mut self) -> Result<(), JoinError> {
+        self.shutdown_graceful()
+    }
+    /// Stops accepting jobs, waits for everything already queued to run and
+    /// then shuts the workers down. Same as `join`, but callable through a
+    /// shared reference.
+    pub fn shutdown_graceful(&self) -> Result<(), JoinError> {
+        self.shared.begin_shutdown(PoolState::ShuttingDown);
+        self.shared.live_workers.wait_zero(None);
+        self.join_workers();
+        self.shared.advance(PoolState::Terminated);
+
+        match self.panicked_jobs() {
+            0 => Ok(()),
+            panicked => Err(JoinError { panicked }),
+        }
+    }
+    /// Stops accepting jobs and returns every job that hasn't started yet.
+    ///
+    /// Workers finish the job they're running and then exit; this waits for
+    /// them to do so. A job that waits for one of the returned jobs, through
+    /// its `TaskHandle` or in `scope`, `fork_join` or `run_graph`, can't
+    /// finish until that job is run or dropped. If there are any, this
+    /// returns without waiting for their workers and leaves the pool
+    /// `Stopping`; `join` then waits for them.
+    pub fn shutdown_now(&self) -> Vec<PendingJob> {
+        self.shared.begin_shutdown(PoolState::Stopping);
+        let mut pending = self.shared.drain();
+        self.shared.working.wait_zero(None);
+        // Jobs may have been queued by submissions that saw the pool running
+        // just before it stopped.
+        while self.shared.submitting.load(Ordering::SeqCst) > 0 {
+            thread::yield_now();
+        }
+        pending.extend(self.shared.drain());
+        if !pending.is_empty() && self.shared.live_workers.get() > 0 {
+            // Every worker left is waiting, and some may be waiting for a
+            // job in `pending`.
+            self.shared.stop_timer();
+            return pending.into_iter().map(PendingJob).collect();
         }
-        let job = Box::new(f);
-        self.sender.send(Message::NewJob(job)).unwrap();
+        self.shared.live_workers.wait_zero(None);
+        self.join_workers();
+        // Running jobs may have queued more work before their worker exited.
+        pending.extend(self.shared.drain());
+        self.shared.advance(PoolState::Terminated);
+        pending.into_iter().map(PendingJob).collect()
     }
-    pub fn join(&mut self) {
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+    /// Shuts down gracefully, but gives up waiting after `timeout`.
+    ///
+    /// If the queue didn't drain in time, the jobs that haven't started are
+    /// returned as the error, and workers still running a job are left to
+    /// finish it in the background.
+    pub fn shutdown_timeout(&self, timeout: Duration) -> Result<(), Vec<PendingJob>> {
+        let deadline = Instant::now() + timeout;
+        self.shared.begin_shutdown(PoolState::ShuttingDown);
+        if self.shared.live_workers.wait_zero(Some(deadline)) {
+            self.join_workers();
+            self.shared.advance(PoolState::Terminated);
+            return Ok(());
         }
 
-        for worker in &mut self.workers {
+        self.shared.advance(PoolState::Stopping);
+        let pending = self.shared.drain();
+        // Don't wait for jobs that overran the deadline: forget their workers.
+        self.shared.workers.lock().unwrap().clear();
+        self.shared.stop_timer();
+        Err(pending.into_iter().map(PendingJob).collect())
+    }
+    /// Blocks until the queue is empty and no worker is running a job.
+    ///
+    /// Unlike `join`, this leaves the pool running, so it can be reused for
+    /// the next batch of jobs.
+    ///
+    /// # Panics
+    ///
+    /// If called from inside a job running on this pool, which would never
+    /// become idle.
+    pub fn wait_idle(&self) {
+        self.assert_not_on_worker("wait_idle");
+        self.shared.unfinished.wait_zero(None);
+    }
+    /// Like `wait_idle`, but gives up after `timeout`. Returns whether the
+    /// pool became idle.
+    pub fn wait_idle_timeout(&self, timeout: Duration) -> bool {
+        self.assert_not_on_worker("wait_idle_timeout");
+        self.shared
+            .unfinished
+            .wait_zero(Some(Instant::now() + timeout))
+    }
+    pub fn state(&self) -> PoolState {
+        self.shared.state()
+    }
+    /// How often the pool's backpressure policy has kicked in.
+    pub fn backpressure_stats(&self) -> BackpressureStats {
+        self.shared.backpressure_counters.snapshot()
+    }
+    /// A snapshot of the pool's queue, job counters and per-worker activity.
+    pub fn metrics(&self) -> PoolMetrics {
+        self.shared.metrics.snapshot(self.shared.queue.len())
+    }
+    /// Number of jobs passed to `execute` that have panicked so far.
+    pub fn panicked_jobs(&self) -> usize {
+        self.shared.panicked.load(Ordering::SeqCst)
+    }
+    /// Number of workers the pool is running with. While shrinking, workers
+    /// that are about to retire aren't counted.
+    pub fn num_threads(&self) -> usize {
+        let max = self.shared.max_workers.load(Ordering::SeqCst);
+        self.shared.live_workers.get().min(max)
+    }
+    /// Grows or shrinks the pool to `num_threads` workers.
+    ///
+    /// New workers start right away. Surplus workers exit once they finish
+    /// their current job, and the jobs still queued are left to the workers
+    /// that remain. Does nothing once the pool is shutting down.
+    ///
+    /// For an elastic pool this sets the number of core workers, raising
+    /// `max_threads` if needed.
+    pub fn resize(&self, num_threads: usize) -> Result<(), BuildError> {
+        if num_threads == 0 {
+            return Err(BuildError::ZeroThreads);
+        }
+        let shared = &self.shared;
+        let mut workers = shared.workers.lock().unwrap();
+        if self.state() != PoolState::Running {
+            return Ok(());
+        }
+        reap_retired(&mut workers);
+
+        let _guard = shared.resize_lock.lock().unwrap();
+        shared.core_workers.store(num_threads, Ordering::SeqCst);
+        if shared.keep_alive.is_some() {
+            shared.max_workers.fetch_max(num_threads, Ordering::SeqCst);
+        } else {
+            shared.max_workers.store(num_threads, Ordering::SeqCst);
+        }
+        while shared.live_workers.get() < num_threads {
+            if let Err(err) = shared.spawn_worker(&mut workers) {
+                let live = shared.live_workers.get();
+                shared.core_workers.store(live, Ordering::SeqCst);
+                shared.max_workers.fetch_min(live, Ordering::SeqCst);
+                return Err(err);
+            }
+        }
+        // Wake parked workers so surplus ones notice they should retire.
+        shared.queue.wake_all();
+        Ok(())
+    }
+
+    fn assert_not_on_worker(&self, method: &str) {
+        let on_worker = worker::with_local(&self.shared, |local| local.is_some());
+        assert!(
+            !on_worker,
+            "ThreadPool::{}() called from a job running on the same pool",
+            method
+        );
+    }
+
+    fn join_workers(&self) {
+        for mut worker in self.shared.workers.lock().unwrap().drain(..) {
             if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
+                let _ = thread.join();
             }
         }
-        self.is_running = false;
+        self.shared.stop_timer();
     }
 }
 
+/// Joins the workers that have retired, after the pool was shrunk or its
+/// extra workers went idle.
+fn reap_retired(workers: &mut Vec<Worker>) {
+    workers.retain_mut(|worker| match worker.thread.take() {
+        Some(thread) if thread.is_finished() => {
+            let _ = thread.join();
+            false
+        }
+        thread => {
+            worker.thread = thread;
+            true
+        }
+    });
+}
+
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        if self.debug {
-            println!("Sending terminate message to all workers.");
-        }
-        if !self.is_running {
-            return;
+        if self.owned && self.state() == PoolState::Running {
+            let _ = self.shutdown_graceful();
         }
-        for _ in &self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+    }
+}
+
+/// A job that was still queued when the pool was shut down with
+/// `ThreadPool::shutdown_now`.
+///
+/// Dropping it discards the job; if it came from `submit`, its handle then
+/// reports `TaskError::Dropped`.
+pub struct PendingJob(Job);
+
+impl PendingJob {
+    /// Runs the job on the current thread.
+    pub fn run(self) {
+        (self.0.run)()
+    }
+}
+
+impl fmt::Debug for PendingJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PendingJob")
+    }
+}
+
+/// Returned by `ThreadPool::try_execute` when a job can't be queued. Holds
+/// the rejected closure.
+pub enum ExecuteError<F> {
+    /// The pool has been shut down.
+    Shutdown(F),
+    /// The pool has no live workers left to run the job.
+    NoWorkers(F),
+    /// The queue is full and the pool uses `BackpressurePolicy::Reject`.
+    Full(F),
+}
+
+impl<F> ExecuteError<F> {
+    /// Returns the closure that was passed to `try_execute`.
+    pub fn into_inner(self) -> F {
+        match self {
+            ExecuteError::Shutdown(f) | ExecuteError::NoWorkers(f) | ExecuteError::Full(f) => f,
         }
-        if self.debug {
-            println!("Shutting down all workers.");
+    }
+}
+
+impl<F> fmt::Debug for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Shutdown(_) => f.write_str("Shutdown(..)"),
+            ExecuteError::NoWorkers(_) => f.write_str("NoWorkers(..)"),
+            ExecuteError::Full(_) => f.write_str("Full(..)"),
         }
-        for worker in &mut self.workers {
-            if self.debug {
-                println!("Shutting down worker {}", worker.id);
-            }
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap();
-            }
+    }
+}
+
+impl<F> fmt::Display for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Shutdown(_) => f.write_str("the thread pool has been shut down"),
+            ExecuteError::NoWorkers(_) => f.write_str("the thread pool has no live workers"),
+            ExecuteError::Full(_) => f.write_str("the thread pool's queue is full"),
         }
     }
 }
-struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+
+impl<F> Error for ExecuteError<F> {}
+
+/// Returned by `ThreadPool::join` when some jobs panicked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JoinError {
+    panicked: usize,
 }
 
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>, debug: bool) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let message = receiver.lock().unwrap().recv().unwrap();
-            match message {
-                Message::NewJob(job) => {
-                    let start = std::time::Instant::now();
-                    if debug {
-                        println!("Worker {} got a job; executing.", id);
-                    }
-                    job();
-                    let duration = start.elapsed();
-                    if debug {
-                        println!(
-                            "Worker {} finished the job in {}ms.",
-                            id,
-                            duration.as_millis()
-                        );
-                    }
-                }
-                Message::Terminate => {
-                    if debug {
-                        println!("Worker {} was told to terminate.", id);
-                    }
-                    break;
-                }
-            }
-        });
-        Worker {
-            id,
-            thread: Some(thread),
-        }
+impl JoinError {
+    pub fn panicked_jobs(&self) -> usize {
+        self.panicked
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} job(s) panicked while the pool was running",
+            self.panicked
+        )
     }
 }
 
-type Job = Box<dyn FnOnce() + Send + 'static>;
+impl Error for JoinError {}
+
+/// How the queue treats a job beyond its priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Admission {
+    /// An ordinary job, subject to the backpressure policy.
+    Detached,
+    /// A job the submitter waits for. It is never rejected or discarded,
+    /// and runs on the submitting thread if it can't be queued.
+    Awaited,
+    /// A job carrying on work the pool already accepted. It may exceed the
+    /// queue's capacity and is never discarded.
+    Resumed,
+}
+
+/// A queued closure together with what the pool tracks about it.
+pub(crate) struct Job {
+    id: u64,
+    queued_at: Instant,
+    priority: Priority,
+    /// Whether `BackpressurePolicy::DropOldest` may discard the job.
+    evictable: bool,
+    run: Box<dyn FnOnce() + Send + 'static>,
+}
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use std::sync::mpsc;
+    use std::thread;
 
     #[test]
     fn it_works() {
@@ -165,7 +1013,7 @@ mod tests {
             let result = a + b;
             assert_eq!(result, 4);
         });
-        pool.join();
+        pool.join().unwrap();
 
         assert_eq!(result, 0);
     }
@@ -180,7 +1028,7 @@ mod tests {
             let result = a + b;
             assert_eq!(result, 4);
         });
-        pool.join();
+        pool.join().unwrap();
         pool.execute(move || {
             let result = a + b;
             assert_eq!(result, 4);
@@ -188,4 +1036,340 @@ mod tests {
 
         assert_eq!(result, 0);
     }
+
+    #[test]
+    fn submit_returns_value() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.submit(|| 1 + 3);
+        assert_eq!(handle.join().unwrap(), 4);
+    }
+
+    #[test]
+    fn submit_delivers_panic_payload() {
+        let pool = ThreadPool::new(2);
+        let handle = pool.submit(|| -> u8 { panic!("boom") });
+        match handle.join() {
+            Err(TaskError::Panicked(payload)) => {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn join_timeout_gives_handle_back() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = pool.submit(move || rx.recv().unwrap());
+        let handle = handle
+            .join_timeout(std::time::Duration::from_millis(10))
+            .unwrap_err();
+        let handle = handle.try_join().unwrap_err();
+        tx.send(()).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn worker_survives_panicking_job() {
+        let mut pool = ThreadPool::new(1);
+        pool.execute(|| panic!("boom"));
+        assert_eq!(pool.submit(|| 7).join().unwrap(), 7);
+        let err = pool.join().unwrap_err();
+        assert_eq!(err.panicked_jobs(), 1);
+    }
+
+    /// Occupies the pool's only worker until the returned sender is used.
+    pub(crate) fn block_worker(pool: &ThreadPool) -> mpsc::Sender<()> {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = release_rx.recv();
+        });
+        started_rx.recv().unwrap();
+        release_tx
+    }
+
+    #[test]
+    fn shutdown_graceful_drains_the_queue() {
+        let pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..10 {
+            let count = Arc::clone(&count);
+            pool.execute(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown_graceful().unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 10);
+        assert_eq!(pool.state(), PoolState::Terminated);
+    }
+
+    #[test]
+    fn shutdown_now_returns_unstarted_jobs() {
+        let pool = ThreadPool::new(1);
+        let release = block_worker(&pool);
+        let handles: Vec<_> = (0..3).map(|i| pool.submit(move || i)).collect();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+        let pending = pool.shutdown_now();
+        assert_eq!(pending.len(), 3);
+        assert_eq!(pool.state(), PoolState::Terminated);
+
+        let mut pending = pending.into_iter();
+        pending.next().unwrap().run();
+        drop(pending);
+        let results: Vec<_> = handles.into_iter().map(TaskHandle::join).collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().any(|r| matches!(r, Err(TaskError::Dropped))));
+    }
+
+    #[test]
+    fn shutdown_timeout_gives_up_on_a_stuck_queue() {
+        let pool = ThreadPool::new(1);
+        let release = block_worker(&pool);
+        pool.execute(|| {});
+        pool.execute(|| {});
+
+        let pending = pool
+            .shutdown_timeout(Duration::from_millis(20))
+            .unwrap_err();
+        assert_eq!(pending.len(), 2);
+        assert_eq!(pool.state(), PoolState::Stopping);
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn try_execute_returns_the_closure_after_shutdown() {
+        let pool = ThreadPool::new(1);
+        pool.shutdown_graceful().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let err = pool.try_execute(move || tx.send(5).unwrap()).unwrap_err();
+        assert!(matches!(err, ExecuteError::Shutdown(_)));
+        err.into_inner()();
+        assert_eq!(rx.recv().unwrap(), 5);
+    }
+
+    #[test]
+    fn wait_idle_leaves_the_pool_running() {
+        let pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicUsize::new(0));
+        for round in 1..=3 {
+            for _ in 0..10 {
+                let count = Arc::clone(&count);
+                pool.execute(move || {
+                    thread::sleep(Duration::from_millis(1));
+                    count.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+            pool.wait_idle();
+            assert_eq!(count.load(Ordering::SeqCst), round * 10);
+        }
+        assert_eq!(pool.state(), PoolState::Running);
+    }
+
+    #[test]
+    fn wait_idle_timeout_reports_a_busy_pool() {
+        let pool = ThreadPool::new(1);
+        let release = block_worker(&pool);
+        assert!(!pool.wait_idle_timeout(Duration::from_millis(10)));
+        release.send(()).unwrap();
+        assert!(pool.wait_idle_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn resize_grows_the_pool() {
+        let pool = ThreadPool::new(1);
+        pool.resize(4).unwrap();
+        assert_eq!(pool.num_threads(), 4);
+        // Only passes if four workers run at once.
+        let barrier = Arc::new(std::sync::Barrier::new(4));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_queued_jobs() {
+        let mut pool = ThreadPool::new(4);
+        let count = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let count = Arc::clone(&count);
+            pool.execute(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.resize(1).unwrap();
+        assert_eq!(pool.num_threads(), 1);
+        pool.wait_idle();
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+
+        while pool.metrics().workers.iter().filter(|w| w.alive).count() > 1 {
+            thread::yield_now();
+        }
+        pool.execute(|| {});
+        pool.join().unwrap();
+        assert_eq!(pool.metrics().completed, 101);
+    }
+
+    #[test]
+    fn elastic_pool_grows_under_load_and_shrinks_when_idle() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        // Only passes if the pool grows to three workers.
+        let barrier = Arc::new(std::sync::Barrier::new(3));
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let barrier = Arc::clone(&barrier);
+                pool.submit(move || {
+                    barrier.wait();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(pool.num_threads(), 3);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.num_threads() > 1 {
+            assert!(Instant::now() < deadline, "extra workers never retired");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(pool.submit(|| 1).join().unwrap(), 1);
+    }
+
+    #[test]
+    fn retired_workers_are_not_kept_around() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(3)
+            .keep_alive(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        for _ in 0..20 {
+            let barrier = Arc::new(std::sync::Barrier::new(3));
+            let handles: Vec<_> = (0..3)
+                .map(|_| {
+                    let barrier = Arc::clone(&barrier);
+                    pool.submit(move || {
+                        barrier.wait();
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+            while pool.num_threads() > 1 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        // The last burst's extra workers may not have been reaped yet.
+        assert!(pool.shared.workers.lock().unwrap().len() <= 3);
+        assert!(pool.metrics().workers.len() <= 3);
+    }
+
+    #[test]
+    fn execute_with_timeout_gives_up_on_slow_jobs() {
+        let pool = ThreadPool::new(1);
+        let slow = pool.execute_with_timeout(Duration::from_millis(20), |token| {
+            while !token.is_cancelled() {
+                thread::yield_now();
+            }
+        });
+        assert!(matches!(slow.join(), Err(TaskError::TimedOut)));
+
+        let fast = pool.execute_with_timeout(Duration::from_secs(5), |_| 7);
+        assert_eq!(fast.join().unwrap(), 7);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.metrics().timed_out == 0 {
+            assert!(Instant::now() < deadline, "overrun was never counted");
+            thread::yield_now();
+        }
+        assert_eq!(pool.metrics().timed_out, 1);
+    }
+
+    #[test]
+    fn timeouts_are_enforced_with_a_full_blocking_queue() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .backpressure(BackpressurePolicy::Block)
+            .build()
+            .unwrap();
+        let (started_tx, started_rx) = mpsc::channel();
+        let slow = pool.execute_with_timeout(Duration::from_millis(20), move |token| {
+            started_tx.send(()).unwrap();
+            while !token.is_cancelled() {
+                thread::yield_now();
+            }
+        });
+        started_rx.recv().unwrap();
+        pool.execute(|| {});
+        // Comes due while the queue is full, so the timer has to queue it
+        // past the capacity rather than wait for room.
+        pool.schedule_after(Duration::ZERO, || {}).unwrap();
+        let result = slow
+            .join_timeout(Duration::from_secs(5))
+            .expect("the timeout never fired");
+        assert!(matches!(result, Err(TaskError::TimedOut)));
+    }
+
+    #[test]
+    fn timeouts_are_enforced_while_draining() {
+        let pool = ThreadPool::new(1);
+        let release = block_worker(&pool);
+        let slow = pool.execute_with_timeout(Duration::from_millis(20), |token| {
+            while !token.is_cancelled() {
+                thread::yield_now();
+            }
+        });
+
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = pool.shutdown_graceful();
+            done_tx.send(()).unwrap();
+        });
+        // Let the shutdown begin before the timed job can start.
+        thread::sleep(Duration::from_millis(20));
+        release.send(()).unwrap();
+        assert!(matches!(slow.join(), Err(TaskError::TimedOut)));
+        done_rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("shutdown hung on the timer");
+    }
+
+    #[test]
+    fn jobs_waiting_on_nested_jobs_do_not_deadlock() {
+        let pool = ThreadPool::new(2);
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                pool.submit(move || {
+                    let pool = ThreadPool::current().unwrap();
+                    let inner: Vec<_> = (0..4).map(|j| pool.submit(move || i * j)).collect();
+                    inner.into_iter().map(|h| h.join().unwrap()).sum::<usize>()
+                })
+            })
+            .collect();
+        let total: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(total, 36);
+        // Dropping the handles from `current` left the pool running.
+        assert_eq!(pool.state(), PoolState::Running);
+    }
 }
@@ -0,0 +1,344 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Number of histogram buckets. Bucket `i` holds durations under `2^i`
+/// microseconds, and the last one holds everything longer.
+const BUCKETS: usize = 32;
+
+/// A point-in-time view of a pool's activity, from `ThreadPool::metrics`.
+///
+/// The counters are read one at a time while the pool keeps running, so
+/// they may be slightly out of step with each other.
+#[derive(Debug, Clone)]
+pub struct PoolMetrics {
+    /// Jobs waiting in the queue.
+    pub queued: usize,
+    /// Jobs a worker is running right now.
+    pub active: usize,
+    /// Jobs that ran to completion without panicking.
+    pub completed: u64,
+    /// Jobs that panicked. Besides jobs passed to `execute`, this counts
+    /// tasks from `submit`, `scope` and the like whose panic is delivered to
+    /// the caller, which `ThreadPool::panicked_jobs` leaves out.
+    pub panicked: u64,
+    /// Submissions the pool refused, for any reason.
+    pub rejected: u64,
+    /// Jobs passed to `execute_with_timeout` that ran past their timeout.
+    pub timed_out: u64,
+    /// One entry per live worker, in start order. Workers that have exited
+    /// are kept until the pool next starts a worker.
+    pub workers: Vec<WorkerMetrics>,
+    /// Time jobs spent in the queue before a worker picked them up.
+    pub queue_wait: Histogram,
+    /// Time jobs spent running, including on the submitting thread.
+    pub execution: Histogram,
+}
+
+/// Activity of a single worker thread.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// The worker's id, as passed to `PoolObserver` and `thread_name`.
+    pub id: usize,
+    /// Jobs this worker has run.
+    pub jobs: u64,
+    /// Total time this worker has spent running jobs.
+    pub busy: Duration,
+    /// Whether the worker thread is still running.
+    pub alive: bool,
+}
+
+/// A histogram of durations with power-of-two buckets, from 1µs up to
+/// about 36 minutes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    sum: Duration,
+}
+
+impl Histogram {
+    /// Number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Sum of every recorded duration.
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    /// Average recorded duration, or `None` if nothing has been recorded.
+    pub fn mean(&self) -> Option<Duration> {
+        match self.count() {
+            0 => None,
+            count => Some(Duration::from_nanos(
+                (self.sum.as_nanos() / count as u128) as u64,
+            )),
+        }
+    }
+
+    /// An upper bound for the `q` quantile, where `q` is between 0 and 1:
+    /// the top of the bucket that holds it. `None` if nothing has been
+    /// recorded.
+    pub fn quantile(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets()
+            .find(|&(_, n)| {
+                seen += n;
+                seen >= rank
+            })
+            .map(|(bound, _)| bound)
+    }
+
+    /// Each bucket's exclusive upper bound with the number of durations in
+    /// it. The last bucket's bound is `Duration::MAX`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| (upper_bound(i), n))
+    }
+}
+
+fn upper_bound(bucket: usize) -> Duration {
+    if bucket == BUCKETS - 1 {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << bucket)
+    }
+}
+
+fn bucket(duration: Duration) -> usize {
+    let micros = duration.as_micros().min(u64::MAX as u128) as u64;
+    ((u64::BITS - micros.leading_zeros()) as usize).min(BUCKETS - 1)
+}
+
+struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    sum_nanos: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn new() -> AtomicHistogram {
+        AtomicHistogram {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, duration: Duration) {
+        self.buckets[bucket(duration)].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        Histogram {
+            buckets: std::array::from_fn(|i| self.buckets[i].load(Ordering::Relaxed)),
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Per-worker counters, shared between the worker thread and the pool.
+pub(crate) struct WorkerStats {
+    id: usize,
+    jobs: AtomicU64,
+    busy_nanos: AtomicU64,
+    alive: AtomicBool,
+}
+
+impl WorkerStats {
+    pub(crate) fn exited(&self) {
+        self.alive.store(false, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> WorkerMetrics {
+        WorkerMetrics {
+            id: self.id,
+            jobs: self.jobs.load(Ordering::Relaxed),
+            busy: Duration::from_nanos(self.busy_nanos.load(Ordering::Relaxed)),
+            alive: self.alive.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The pool's counters behind `PoolMetrics`.
+pub(crate) struct Recorder {
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+    queue_wait: AtomicHistogram,
+    execution: AtomicHistogram,
+    workers: Mutex<Vec<Arc<WorkerStats>>>,
+}
+
+impl Recorder {
+    pub(crate) fn new() -> Recorder {
+        Recorder {
+            active: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+            queue_wait: AtomicHistogram::new(),
+            execution: AtomicHistogram::new(),
+            workers: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a worker to the list, dropping the ones that have exited so an
+    /// elastic pool's list doesn't grow forever.
+    pub(crate) fn add_worker(&self, id: usize) -> Arc<WorkerStats> {
+        let stats = Arc::new(WorkerStats {
+            id,
+            jobs: AtomicU64::new(0),
+            busy_nanos: AtomicU64::new(0),
+            alive: AtomicBool::new(true),
+        });
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|worker| worker.alive.load(Ordering::Relaxed));
+        workers.push(Arc::clone(&stats));
+        stats
+    }
+
+    pub(crate) fn active(&self) -> usize {
+        self.active.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn rejected(&self) {
+        self.rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn timed_out(&self) {
+        self.timed_out.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn completed(&self) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn panicked(&self) {
+        self.panicked.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A job ran on the submitting thread instead of a worker.
+    pub(crate) fn ran_inline(&self, duration: Duration) {
+        self.execution.record(duration);
+    }
+
+    pub(crate) fn job_started(&self, queue_wait: Duration) {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        self.queue_wait.record(queue_wait);
+    }
+
+    pub(crate) fn job_finished(&self, worker: &WorkerStats, duration: Duration) {
+        self.execution.record(duration);
+        worker.jobs.fetch_add(1, Ordering::Relaxed);
+        worker
+            .busy_nanos
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn snapshot(&self, queued: usize) -> PoolMetrics {
+        PoolMetrics {
+            queued,
+            active: self.active(),
+            completed: self.completed.load(Ordering::Relaxed),
+            panicked: self.panicked.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+            workers: self
+                .workers
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|worker| worker.snapshot())
+                .collect(),
+            queue_wait: self.queue_wait.snapshot(),
+            execution: self.execution.snapshot(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::block_worker;
+    use crate::{BackpressurePolicy, ThreadPool, ThreadPoolBuilder};
+
+    #[test]
+    fn histogram_quantiles_use_bucket_bounds() {
+        let histogram = AtomicHistogram::new();
+        for micros in [0, 1, 3, 3, 100] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let histogram = histogram.snapshot();
+        assert_eq!(histogram.count(), 5);
+        assert_eq!(histogram.sum(), Duration::from_micros(107));
+        assert_eq!(histogram.quantile(0.0), Some(Duration::from_micros(1)));
+        assert_eq!(histogram.quantile(0.5), Some(Duration::from_micros(4)));
+        assert_eq!(histogram.quantile(1.0), Some(Duration::from_micros(128)));
+        assert_eq!(AtomicHistogram::new().snapshot().mean(), None);
+    }
+
+    #[test]
+    fn metrics_count_finished_jobs() {
+        let mut pool = ThreadPool::new(2);
+        for _ in 0..10 {
+            pool.execute(|| {});
+        }
+        pool.execute(|| panic!("counted"));
+        pool.wait_idle();
+
+        let metrics = pool.metrics();
+        assert_eq!(metrics.queued, 0);
+        assert_eq!(metrics.active, 0);
+        assert_eq!(metrics.completed, 10);
+        assert_eq!(metrics.panicked, 1);
+        assert_eq!(metrics.queue_wait.count(), 11);
+        assert_eq!(metrics.execution.count(), 11);
+        assert_eq!(metrics.workers.len(), 2);
+        assert_eq!(metrics.workers.iter().map(|w| w.jobs).sum::<u64>(), 11);
+
+        let _ = pool.join();
+        assert_eq!(pool.metrics().rejected, 0);
+        assert!(pool.try_execute(|| {}).is_err());
+        let metrics = pool.metrics();
+        assert_eq!(metrics.rejected, 1);
+        assert!(metrics.workers.iter().all(|w| !w.alive));
+    }
+
+    #[test]
+    fn caught_and_inline_jobs_are_counted() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .queue_capacity(1)
+            .backpressure(BackpressurePolicy::CallerRuns)
+            .build()
+            .unwrap();
+        assert!(pool.submit(|| panic!("caught")).join().is_err());
+        pool.wait_idle();
+        let metrics = pool.metrics();
+        assert_eq!((metrics.completed, metrics.panicked), (0, 1));
+        assert_eq!(pool.panicked_jobs(), 0);
+
+        let release = block_worker(&pool);
+        for _ in 0..5 {
+            pool.execute(|| {});
+        }
+        release.send(()).unwrap();
+        pool.wait_idle();
+        let metrics = pool.metrics();
+        assert_eq!(metrics.completed + metrics.panicked, 7);
+        assert_eq!(metrics.execution.count(), 7);
+    }
+}
@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+/// Receives events about a pool's workers and jobs.
+///
+/// Every method has an empty default, so an observer only implements the
+/// events it cares about. Methods are called on the thread where the event
+/// happens, usually a worker, so they should be quick and must not panic.
+///
+/// ```
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use multithreading::{PoolObserver, ThreadPoolBuilder};
+///
+/// #[derive(Default)]
+/// struct JobCounter(AtomicUsize);
+///
+/// impl PoolObserver for JobCounter {
+///     fn on_job_start(&self, _worker: usize) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// let counter = Arc::new(JobCounter::default());
+/// let mut pool = ThreadPoolBuilder::new()
+///     .num_threads(2)
+///     .observer(counter.clone())
+///     .build()
+///     .unwrap();
+/// pool.execute(|| {});
+/// pool.join().unwrap();
+/// assert_eq!(counter.0.load(Ordering::Relaxed), 1);
+/// ```
+pub trait PoolObserver: Send + Sync {
+    /// A worker thread has started.
+    fn on_worker_spawn(&self, _worker: usize) {}
+    /// A worker thread is about to exit.
+    fn on_worker_exit(&self, _worker: usize) {}
+    /// A worker picked up a job.
+    fn on_job_start(&self, _worker: usize) {}
+    /// A worker finished a job, whether or not it panicked.
+    fn on_job_end(&self, _worker: usize, _duration: Duration) {}
+    /// A job panicked; the worker caught the panic and carries on. Also
+    /// called for tasks that caught their own panic to deliver it to their
+    /// caller, like `submit` jobs.
+    fn on_job_panic(&self, _worker: usize) {}
+    /// A job passed to `execute_with_timeout` was still running after
+    /// `timeout`. Called from the pool's timer thread.
+    fn on_job_timeout(&self, _timeout: Duration) {}
+    /// A worker noticed the pool is shutting down and is stopping.
+    fn on_terminate(&self, _worker: usize) {}
+    /// The pool started shutting down.
+    fn on_shutdown(&self) {}
+}
+
+/// Prints every event to stdout. Installed by `ThreadPoolBuilder::debug`.
+pub(crate) struct DebugObserver;
+
+impl PoolObserver for DebugObserver {
+    fn on_worker_spawn(&self, worker: usize) {
+        println!("Worker {} started.", worker);
+    }
+
+    fn on_worker_exit(&self, worker: usize) {
+        println!("Shutting down worker {}", worker);
+    }
+
+    fn on_job_start(&self, worker: usize) {
+        println!("Worker {} got a job; executing.", worker);
+    }
+
+    fn on_job_end(&self, worker: usize, duration: Duration) {
+        println!(
+            "Worker {} finished the job in {}ms.",
+            worker,
+            duration.as_millis()
+        );
+    }
+
+    fn on_job_panic(&self, worker: usize) {
+        println!("Worker {} caught a panicking job.", worker);
+    }
+
+    fn on_job_timeout(&self, timeout: Duration) {
+        println!("A job overran its {}ms timeout.", timeout.as_millis());
+    }
+
+    fn on_terminate(&self, worker: usize) {
+        println!("Worker {} was told to terminate.", worker);
+    }
+
+    fn on_shutdown(&self) {
+        println!("Shutting down all workers.");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ThreadPoolBuilder;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl PoolObserver for Recorder {
+        fn on_worker_spawn(&self, worker: usize) {
+            self.0.lock().unwrap().push(format!("spawn {}", worker));
+        }
+        fn on_worker_exit(&self, worker: usize) {
+            self.0.lock().unwrap().push(format!("exit {}", worker));
+        }
+        fn on_job_start(&self, worker: usize) {
+            self.0.lock().unwrap().push(format!("start {}", worker));
+        }
+        fn on_job_end(&self, worker: usize, _duration: Duration) {
+            self.0.lock().unwrap().push(format!("end {}", worker));
+        }
+        fn on_job_panic(&self, worker: usize) {
+            self.0.lock().unwrap().push(format!("panic {}", worker));
+        }
+        fn on_terminate(&self, worker: usize) {
+            self.0.lock().unwrap().push(format!("terminate {}", worker));
+        }
+        fn on_shutdown(&self) {
+            self.0.lock().unwrap().push("shutdown".to_string());
+        }
+    }
+
+    #[test]
+    fn observer_sees_the_worker_lifecycle() {
+        let recorder = Arc::new(Recorder::default());
+        let mut pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .observer(recorder.clone())
+            .build()
+            .unwrap();
+        pool.execute(|| {});
+        pool.execute(|| panic!("observed"));
+        pool.wait_idle();
+        let _ = pool.join();
+
+        let events = recorder.0.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                "spawn 0",
+                "start 0",
+                "end 0",
+                "start 0",
+                "panic 0",
+                "end 0",
+                "shutdown",
+                "terminate 0",
+                "exit 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,8 @@
+//! Linux calls shared by the `affinity` and `thread-priority` features.
+
+/// The kernel's id for the calling thread, which the scheduling syscalls
+/// take in place of a pid.
+pub(crate) fn thread_id() -> libc::pid_t {
+    // SAFETY: gettid takes no arguments and can't fail.
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
@@ -0,0 +1,76 @@
+/// How urgently a job should run, for `ThreadPool::execute_with_priority`.
+///
+/// Workers always take a queued job of a higher priority first. Jobs passed
+/// to `execute`, `submit` or a scope are `Normal`. To keep a steady stream of
+/// urgent jobs from starving the rest, see `ThreadPoolBuilder::priority_aging`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    /// Runs once no normal or high priority job is waiting.
+    Low,
+    /// The priority of jobs submitted without one.
+    #[default]
+    Normal,
+    /// Runs before any waiting normal or low priority job.
+    High,
+}
+
+impl Priority {
+    pub(crate) const COUNT: usize = 3;
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::block_worker;
+    use crate::ThreadPoolBuilder;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    /// A one-worker pool whose worker is busy until the returned sender is used.
+    fn blocked_pool(builder: ThreadPoolBuilder) -> (crate::ThreadPool, mpsc::Sender<()>) {
+        let pool = builder.num_threads(1).build().unwrap();
+        let release = block_worker(&pool);
+        (pool, release)
+    }
+
+    #[test]
+    fn higher_priorities_run_first() {
+        let (pool, release) = blocked_pool(ThreadPoolBuilder::new());
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let order = Arc::clone(&order);
+            pool.execute_with_priority(priority, move || order.lock().unwrap().push(priority));
+        }
+        release.send(()).unwrap();
+        pool.wait_idle();
+        assert_eq!(
+            *order.lock().unwrap(),
+            [Priority::High, Priority::Normal, Priority::Low]
+        );
+    }
+
+    #[test]
+    fn aging_lets_starved_jobs_through() {
+        let (pool, release) =
+            blocked_pool(ThreadPoolBuilder::new().priority_aging(Duration::from_millis(20)));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let low = Arc::clone(&order);
+        pool.execute_with_priority(Priority::Low, move || {
+            low.lock().unwrap().push(Priority::Low)
+        });
+        for _ in 0..2 {
+            let high = Arc::clone(&order);
+            pool.execute_with_priority(Priority::High, move || {
+                high.lock().unwrap().push(Priority::High)
+            });
+        }
+        std::thread::sleep(Duration::from_millis(30));
+        release.send(()).unwrap();
+        pool.wait_idle();
+        assert_eq!(order.lock().unwrap()[0], Priority::Low);
+    }
+}
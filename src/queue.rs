@@ -0,0 +1,421 @@
+use std::sync::atomic::{self, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crossbeam_deque::{Injector, Steal, Stealer, Worker as Deque};
+use crossbeam_epoch::{self as epoch, Atomic, Guard, Owned};
+
+use crate::{Job, Priority};
+
+/// The pool's job queue: one deque per worker plus a global injector per
+/// priority.
+///
+/// Normal jobs submitted from outside the pool go to the normal injector.
+/// Normal jobs submitted from inside a job go to the deque of the worker
+/// running it. High and low priority jobs always go to their injector. An
+/// idle worker takes a high priority job first, then one from its own
+/// deque, the normal injector or another worker's deque, in that order, and
+/// only then a low priority job.
+///
+/// With `aging`, a low or normal priority level that has had jobs waiting
+/// without being served for that long is served first.
+///
+/// `len` counts the jobs that are queued but not yet taken by a worker. A
+/// slot has to be reserved with `reserve` before every `push`.
+pub(crate) struct Queue {
+    injectors: [Injector<Job>; Priority::COUNT],
+    /// The workers' stealers. Readers load the current list without
+    /// locking; `register` and `retire` replace it with an updated copy,
+    /// and the old one is freed once no reader can still hold it.
+    stealers: Atomic<Vec<Slot>>,
+    /// Serializes the writers of `stealers`.
+    resizing: Mutex<()>,
+    len: AtomicUsize,
+    sleep: Sleep,
+    space: Sleep,
+    aging: Option<Duration>,
+    /// When each priority level was last served, or became non-empty, in
+    /// nanoseconds since `epoch`. Only kept up to date with `aging`.
+    served: [AtomicU64; Priority::COUNT],
+    epoch: Instant,
+}
+
+/// A worker's stealer. A retired worker's slot stays registered, so the
+/// jobs left in its deque can still be stolen, and is reused by the next
+/// worker once that deque is empty.
+#[derive(Clone)]
+struct Slot {
+    stealer: Stealer<Job>,
+    retired: bool,
+}
+
+/// Parks threads until they may be able to make progress: idle workers
+/// waiting for a job, or producers waiting for room in a full queue.
+struct Sleep {
+    lock: Mutex<()>,
+    wake: Condvar,
+    sleepers: AtomicUsize,
+}
+
+impl Sleep {
+    fn new() -> Sleep {
+        Sleep {
+            lock: Mutex::new(()),
+            wake: Condvar::new(),
+            sleepers: AtomicUsize::new(0),
+        }
+    }
+
+    fn notify_one(&self) {
+        atomic::fence(Ordering::SeqCst);
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            let _guard = self.lock.lock().unwrap();
+            self.wake.notify_one();
+        }
+    }
+
+    fn notify_all(&self) {
+        let _guard = self.lock.lock().unwrap();
+        self.wake.notify_all();
+    }
+
+    /// Waits for a notification unless `ready` already returns true, giving
+    /// up after `timeout` if given. Returns whether the wait timed out.
+    fn wait_unless(&self, ready: impl Fn() -> bool, timeout: Option<Duration>) -> bool {
+        let guard = self.lock.lock().unwrap();
+        self.sleepers.fetch_add(1, Ordering::SeqCst);
+        atomic::fence(Ordering::SeqCst);
+        let mut timed_out = false;
+        if !ready() {
+            match timeout {
+                Some(timeout) => {
+                    let (guard, result) = self.wake.wait_timeout(guard, timeout).unwrap();
+                    drop(guard);
+                    timed_out = result.timed_out();
+                }
+                None => drop(self.wake.wait(guard).unwrap()),
+            }
+        }
+        self.sleepers.fetch_sub(1, Ordering::SeqCst);
+        timed_out
+    }
+}
+
+impl Queue {
+    pub(crate) fn new(aging: Option<Duration>) -> Queue {
+        Queue {
+            injectors: std::array::from_fn(|_| Injector::new()),
+            stealers: Atomic::new(Vec::new()),
+            resizing: Mutex::new(()),
+            len: AtomicUsize::new(0),
+            sleep: Sleep::new(),
+            space: Sleep::new(),
+            aging,
+            served: std::array::from_fn(|_| AtomicU64::new(0)),
+            epoch: Instant::now(),
+        }
+    }
+
+    fn injector(&self, priority: Priority) -> &Injector<Job> {
+        &self.injectors[priority.index()]
+    }
+
+    fn mark_served(&self, priority: Priority) {
+        if self.aging.is_some() {
+            let now = self.epoch.elapsed().as_nanos() as u64;
+            self.served[priority.index()].store(now, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Reserves a slot for one job. Fails if `capacity` jobs are already queued.
+    pub(crate) fn reserve(&self, capacity: Option<usize>) -> bool {
+        match capacity {
+            None => {
+                self.len.fetch_add(1, Ordering::SeqCst);
+                true
+            }
+            Some(capacity) => self
+                .len
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |len| {
+                    (len < capacity).then_some(len + 1)
+                })
+                .is_ok(),
+        }
+    }
+
+    /// Blocks until a slot is reserved, or returns false once `keep_waiting` does.
+    pub(crate) fn reserve_blocking(
+        &self,
+        capacity: usize,
+        keep_waiting: impl Fn() -> bool,
+    ) -> bool {
+        loop {
+            if self.reserve(Some(capacity)) {
+                return true;
+            }
+            if !keep_waiting() {
+                return false;
+            }
+            self.space
+                .wait_unless(|| self.len() < capacity || !keep_waiting(), None);
+        }
+    }
+
+    fn taken(&self, count: usize) {
+        self.len.fetch_sub(count, Ordering::SeqCst);
+        self.space.notify_one();
+    }
+
+    /// The current list of stealers, valid for as long as `guard` is held.
+    fn stealers<'g>(&self, guard: &'g Guard) -> &'g [Slot] {
+        let stealers = self.stealers.load(Ordering::Acquire, guard);
+        // SAFETY: the list is never null, and a replaced list is only freed
+        // once every guard that could have loaded it is gone.
+        unsafe { stealers.deref() }
+    }
+
+    /// Replaces the list of stealers with a copy changed by `update`, and
+    /// returns what `update` returned.
+    fn update_stealers<R>(&self, update: impl FnOnce(&mut Vec<Slot>) -> R) -> R {
+        let _resizing = self.resizing.lock().unwrap();
+        let guard = &epoch::pin();
+        let mut stealers = self.stealers(guard).to_vec();
+        let result = update(&mut stealers);
+        let old = self
+            .stealers
+            .swap(Owned::new(stealers), Ordering::AcqRel, guard);
+        // SAFETY: `old` is no longer reachable from `self.stealers`.
+        unsafe { guard.defer_destroy(old) };
+        result
+    }
+
+    /// Creates the deque for a new worker and registers its stealer.
+    pub(crate) fn register(&self) -> (usize, Deque<Job>) {
+        let local = Deque::new_lifo();
+        let slot = Slot {
+            stealer: local.stealer(),
+            retired: false,
+        };
+        let index = self.update_stealers(|stealers| {
+            let free = stealers
+                .iter()
+                .position(|slot| slot.retired && slot.stealer.is_empty());
+            match free {
+                Some(index) => {
+                    stealers[index] = slot;
+                    index
+                }
+                None => {
+                    stealers.push(slot);
+                    stealers.len() - 1
+                }
+            }
+        });
+        (index, local)
+    }
+
+    /// Marks the slot of a worker that is exiting for good. Whatever is left
+    /// in its deque stays there for the other workers to steal.
+    pub(crate) fn retire(&self, index: usize) {
+        self.update_stealers(|stealers| stealers[index].retired = true);
+    }
+
+    /// Pushes a normal priority job onto `local` if given, and any other job
+    /// onto the injector for its priority.
+    pub(crate) fn push(&self, job: Job, local: Option<&Deque<Job>>) {
+        match local {
+            Some(local) if job.priority == Priority::Normal => local.push(job),
+            _ => {
+                let injector = self.injector(job.priority);
+                if injector.is_empty() {
+                    // Jobs of this priority only start waiting now.
+                    self.mark_served(job.priority);
+                }
+                injector.push(job);
+            }
+        }
+        self.sleep.notify_one();
+    }
+
+    /// Wakes every parked worker and blocked producer so they can notice a
+    /// change of pool state.
+    pub(crate) fn wake_all(&self) {
+        self.sleep.notify_all();
+        self.space.notify_all();
+    }
+
+    fn has_work(&self) -> bool {
+        self.injectors.iter().any(|injector| !injector.is_empty())
+            || self
+                .stealers(&epoch::pin())
+                .iter()
+                .any(|slot| !slot.stealer.is_empty())
+    }
+
+    /// Finds the next job for the worker at `index`, whose deque is `local`.
+    pub(crate) fn find(&self, index: usize, local: &Deque<Job>) -> Option<Job> {
+        let job = self.steal(index, local);
+        if job.is_some() {
+            self.taken(1);
+        }
+        job
+    }
+
+    fn steal(&self, index: usize, local: &Deque<Job>) -> Option<Job> {
+        if let Some(job) = self.steal_starved() {
+            return Some(job);
+        }
+        if let Some(job) = self.steal_from(Priority::High) {
+            return Some(job);
+        }
+        if let Some(job) = self.steal_normal(index, local) {
+            self.mark_served(Priority::Normal);
+            return Some(job);
+        }
+        self.steal_from(Priority::Low)
+    }
+
+    /// Takes a job from a low or normal priority injector that hasn't been
+    /// served for longer than `aging`.
+    fn steal_starved(&self) -> Option<Job> {
+        let aging = self.aging?.as_nanos() as u64;
+        let now = self.epoch.elapsed().as_nanos() as u64;
+        [Priority::Low, Priority::Normal]
+            .into_iter()
+            .filter(|&priority| !self.injector(priority).is_empty())
+            .filter(|&priority| {
+                let served = self.served[priority.index()].load(Ordering::Relaxed);
+                now.saturating_sub(served) >= aging
+            })
+            .find_map(|priority| self.steal_from(priority))
+    }
+
+    fn steal_from(&self, priority: Priority) -> Option<Job> {
+        let job = steal_one(|| self.injector(priority).steal());
+        if job.is_some() {
+            self.mark_served(priority);
+        }
+        job
+    }
+
+    fn steal_normal(&self, index: usize, local: &Deque<Job>) -> Option<Job> {
+        if let Some(job) = local.pop() {
+            return Some(job);
+        }
+        loop {
+            let mut retry = false;
+            match self.injector(Priority::Normal).steal_batch_and_pop(local) {
+                Steal::Success(job) => return Some(job),
+                Steal::Retry => retry = true,
+                Steal::Empty => {}
+            }
+            let guard = &epoch::pin();
+            let (after, before) = self.stealers(guard).split_at(index + 1);
+            for slot in after.iter().chain(before) {
+                match slot.stealer.steal_batch_and_pop(local) {
+                    Steal::Success(job) => return Some(job),
+                    Steal::Retry => retry = true,
+                    Steal::Empty => {}
+                }
+            }
+            if !retry {
+                return None;
+            }
+        }
+    }
+
+    /// Removes every job that hasn't been started yet.
+    pub(crate) fn drain(&self) -> Vec<Job> {
+        let mut jobs = Vec::new();
+        for injector in &self.injectors {
+            while let Some(job) = steal_one(|| injector.steal()) {
+                jobs.push(job);
+            }
+        }
+        for slot in self.stealers(&epoch::pin()) {
+            while let Some(job) = steal_one(|| slot.stealer.steal()) {
+                jobs.push(job);
+            }
+        }
+        self.taken(jobs.len());
+        jobs
+    }
+
+    /// Removes the oldest job of the lowest priority that hasn't been
+    /// started yet.
+    pub(crate) fn pop_oldest(&self) -> Option<Job> {
+        let oldest_of = |priority| steal_one(|| self.injector(priority).steal());
+        let job = oldest_of(Priority::Low)
+            .or_else(|| oldest_of(Priority::Normal))
+            .or_else(|| {
+                self.stealers(&epoch::pin())
+                    .iter()
+                    .find_map(|slot| steal_one(|| slot.stealer.steal()))
+            })
+            .or_else(|| oldest_of(Priority::High));
+        if job.is_some() {
+            self.taken(1);
+        }
+        job
+    }
+
+    /// Parks the calling worker until a job is pushed, `wake_all` is called
+    /// or `timeout` passes, unless there's already work or `keep_sleeping`
+    /// returns false. Returns whether the timeout passed.
+    pub(crate) fn park(&self, keep_sleeping: impl Fn() -> bool, timeout: Option<Duration>) -> bool {
+        self.sleep
+            .wait_unless(|| self.has_work() || !keep_sleeping(), timeout)
+    }
+}
+
+impl Drop for Queue {
+    fn drop(&mut self) {
+        // SAFETY: with `&mut self`, no other thread can be reading the list.
+        unsafe {
+            drop(
+                self.stealers
+                    .load(Ordering::Relaxed, epoch::unprotected())
+                    .into_owned(),
+            )
+        };
+    }
+}
+
+/// Retries `steal` until it either succeeds or finds the source empty.
+fn steal_one(steal: impl Fn() -> Steal<Job>) -> Option<Job> {
+    loop {
+        match steal() {
+            Steal::Success(job) => return Some(job),
+            Steal::Retry => continue,
+            Steal::Empty => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ThreadPool;
+    use std::sync::Barrier;
+
+    #[test]
+    fn nested_jobs_are_stolen_by_idle_workers() {
+        let pool = ThreadPool::new(4);
+        let barrier = Barrier::new(4);
+        pool.scope(|s| {
+            s.spawn(|| {
+                // These land on this worker's own deque, and it is blocked
+                // below until the other three workers have stolen them.
+                for _ in 0..3 {
+                    s.spawn(|| {
+                        barrier.wait();
+                    });
+                }
+                barrier.wait();
+            });
+        });
+    }
+}
@@ -0,0 +1,222 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::{worker, ThreadPool};
+
+/// A scope for spawning jobs that borrow from the caller's stack.
+///
+/// Created by `ThreadPool::scope`.
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+struct ScopeState {
+    pending: Mutex<usize>,
+    done: Condvar,
+    panic: Mutex<Option<Box<dyn Any + Send + 'static>>>,
+}
+
+impl ScopeState {
+    fn record_panic(&self, payload: Box<dyn Any + Send + 'static>) {
+        let mut panic = self.panic.lock().unwrap();
+        if panic.is_none() {
+            *panic = Some(payload);
+        }
+    }
+
+    /// Waits for every job in the scope. A worker runs other jobs meanwhile.
+    fn wait(&self) {
+        worker::help_until(|| *self.pending.lock().unwrap() == 0);
+        let mut pending = self.pending.lock().unwrap();
+        while *pending > 0 {
+            pending = self.done.wait(pending).unwrap();
+        }
+    }
+}
+
+/// Runs the closure and then marks it as finished in its scope.
+///
+/// The bookkeeping lives in `Drop` so that a job the pool drops without
+/// running still releases the scope. The closure is always dropped before the
+/// count is decremented, because that's the last point it may touch borrowed
+/// data.
+struct ScopedJob<F> {
+    f: Option<F>,
+    state: Arc<ScopeState>,
+}
+
+impl<F: FnOnce()> ScopedJob<F> {
+    fn run(mut self) {
+        if let Some(f) = self.f.take() {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                worker::report_caught_panic();
+                self.state.record_panic(payload);
+            }
+        }
+    }
+}
+
+impl<F> Drop for ScopedJob<F> {
+    fn drop(&mut self) {
+        drop(self.f.take());
+        let mut pending = self.state.pending.lock().unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.done.notify_all();
+        }
+    }
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Runs `f` on the pool. The job may borrow anything that outlives the scope.
+    ///
+    /// If the pool rejects the job, because it's shutting down or its queue
+    /// is full, `f` runs on the calling thread instead.
+    pub fn spawn<F>(&'scope self, f: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        *self.state.pending.lock().unwrap() += 1;
+        let job = ScopedJob {
+            f: Some(f),
+            state: Arc::clone(&self.state),
+        };
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || job.run());
+        // SAFETY: `ThreadPool::scope` doesn't return until every `ScopedJob`
+        // has been dropped, so the closure never outlives 'scope.
+        let job: Box<dyn FnOnce() + Send + 'static> = unsafe { mem::transmute(job) };
+        // A job the queue won't take runs here instead, so it still finishes
+        // before the scope returns.
+        self.pool.shared.submit_awaited(job);
+    }
+}
+
+impl ThreadPool {
+    /// Creates a scope in which jobs may borrow non-`'static` data.
+    ///
+    /// Every job spawned in the scope has finished by the time this returns.
+    /// If any of them panicked, the panic is resumed on the calling thread.
+    ///
+    /// ```
+    /// use multithreading::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(4);
+    /// let mut numbers = vec![1, 2, 3, 4];
+    /// pool.scope(|s| {
+    ///     for n in numbers.iter_mut() {
+    ///         s.spawn(move || *n *= 2);
+    ///     }
+    /// });
+    /// assert_eq!(numbers, [2, 4, 6, 8]);
+    /// ```
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                panic: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.state.wait();
+        if let Some(payload) = scope.state.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        match result {
+            Ok(value) => value,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BackpressurePolicy, ThreadPoolBuilder};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn jobs_borrow_from_the_stack() {
+        let pool = ThreadPool::new(4);
+        let data = vec![1, 2, 3, 4, 5];
+        let sum = AtomicUsize::new(0);
+        pool.scope(|s| {
+            for n in &data {
+                let sum = &sum;
+                s.spawn(move || {
+                    sum.fetch_add(*n, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(sum.into_inner(), 15);
+    }
+
+    #[test]
+    fn nested_spawns_finish_before_return() {
+        let pool = ThreadPool::new(2);
+        let count = AtomicUsize::new(0);
+        pool.scope(|s| {
+            s.spawn(|| {
+                s.spawn(|| {
+                    count.fetch_add(1, Ordering::SeqCst);
+                });
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+        assert_eq!(count.into_inner(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "scoped boom")]
+    fn panics_propagate() {
+        let pool = ThreadPool::new(2);
+        pool.scope(|s| {
+            s.spawn(|| panic!("scoped boom"));
+        });
+    }
+
+    #[test]
+    fn every_job_runs_when_the_queue_is_full() {
+        for policy in [BackpressurePolicy::Reject, BackpressurePolicy::DropOldest] {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(2)
+                .queue_capacity(1)
+                .backpressure(policy)
+                .build()
+                .unwrap();
+            let count = AtomicUsize::new(0);
+            pool.scope(|s| {
+                for _ in 0..100 {
+                    s.spawn(|| {
+                        count.fetch_add(1, Ordering::SeqCst);
+                    });
+                }
+            });
+            assert_eq!(count.into_inner(), 100, "{:?}", policy);
+
+            let squares = pool.map(0..1000, |n| n * n);
+            assert_eq!(squares.len(), 1000, "{:?}", policy);
+            assert_eq!(squares[999], 999 * 999);
+
+            // Jobs run on the caller instead are counted as run, not rejected.
+            pool.wait_idle();
+            let metrics = pool.metrics();
+            assert_eq!(metrics.rejected, 0, "{:?}", policy);
+            assert_eq!(pool.backpressure_stats().rejected, 0, "{:?}", policy);
+            assert_eq!(metrics.completed, metrics.execution.count(), "{:?}", policy);
+            assert!(metrics.completed >= 100, "{:?}", policy);
+        }
+    }
+}
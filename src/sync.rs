@@ -0,0 +1,54 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Instant;
+
+/// An atomic counter that threads can wait on until it drops to zero.
+pub(crate) struct WaitCounter {
+    count: AtomicUsize,
+    lock: Mutex<()>,
+    zero: Condvar,
+}
+
+impl WaitCounter {
+    pub(crate) fn new() -> WaitCounter {
+        WaitCounter {
+            count: AtomicUsize::new(0),
+            lock: Mutex::new(()),
+            zero: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn get(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn add(&self, n: usize) {
+        self.count.fetch_add(n, Ordering::SeqCst);
+    }
+
+    pub(crate) fn sub(&self, n: usize) {
+        if n > 0 && self.count.fetch_sub(n, Ordering::SeqCst) == n {
+            let _guard = self.lock.lock().unwrap();
+            self.zero.notify_all();
+        }
+    }
+
+    /// Waits until the count is zero, or until `deadline` passes. Returns
+    /// whether the count reached zero.
+    pub(crate) fn wait_zero(&self, deadline: Option<Instant>) -> bool {
+        let mut guard = self.lock.lock().unwrap();
+        while self.get() > 0 {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    guard = self.zero.wait_timeout(guard, deadline - now).unwrap().0;
+                }
+                None => guard = self.zero.wait(guard).unwrap(),
+            }
+        }
+        true
+    }
+}
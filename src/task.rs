@@ -0,0 +1,258 @@
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use crate::{worker, CancellationToken};
+
+/// Why a task submitted with `ThreadPool::submit` did not produce a value.
+pub enum TaskError {
+    /// The closure panicked; holds the panic payload.
+    Panicked(Box<dyn Any + Send + 'static>),
+    /// The job was dropped by the pool before it could run.
+    Dropped,
+    /// The task was cancelled before it started.
+    Cancelled,
+    /// The task ran past its timeout. It was asked to stop through its
+    /// `CancellationToken`, and whatever it returns later is discarded.
+    TimedOut,
+}
+
+impl fmt::Debug for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Panicked(_) => f.write_str("Panicked(..)"),
+            TaskError::Dropped => f.write_str("Dropped"),
+            TaskError::Cancelled => f.write_str("Cancelled"),
+            TaskError::TimedOut => f.write_str("TimedOut"),
+        }
+    }
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskError::Panicked(_) => f.write_str("task panicked"),
+            TaskError::Dropped => f.write_str("task was dropped before it could run"),
+            TaskError::Cancelled => f.write_str("task was cancelled before it started"),
+            TaskError::TimedOut => f.write_str("task timed out"),
+        }
+    }
+}
+
+impl Error for TaskError {}
+
+struct Packet<T> {
+    result: Mutex<Option<Result<T, TaskError>>>,
+    done: Condvar,
+    resolved: AtomicBool,
+    /// Set by a pending `poll`; only touched with `result` locked.
+    waker: Mutex<Option<Waker>>,
+}
+
+impl<T> Packet<T> {
+    /// Stores the task's result, unless it already has one. Returns whether
+    /// it did.
+    fn resolve(&self, result: Result<T, TaskError>) -> bool {
+        if self.resolved.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        let mut slot = self.result.lock().unwrap();
+        *slot = Some(result);
+        let waker = self.waker.lock().unwrap().take();
+        drop(slot);
+        self.done.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+        true
+    }
+}
+
+/// The worker side of a task: stores the result and wakes the handle.
+///
+/// Dropping it without calling `complete` resolves the handle with
+/// `TaskError::Dropped`, so a job that never runs can't leave `join` hanging.
+pub(crate) struct Completer<T> {
+    packet: Option<Arc<Packet<T>>>,
+    token: CancellationToken,
+}
+
+impl<T> Completer<T> {
+    /// Runs `f` with the task's token and completes the task with its
+    /// result, unless the task was cancelled first.
+    pub(crate) fn run(self, f: impl FnOnce(&CancellationToken) -> T) {
+        if self.token.is_cancelled() {
+            return self.complete(Err(TaskError::Cancelled));
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&self.token)));
+        if result.is_err() {
+            worker::report_caught_panic();
+        }
+        self.complete(result.map_err(TaskError::Panicked));
+    }
+
+    pub(crate) fn complete(mut self, result: Result<T, TaskError>) {
+        if let Some(packet) = self.packet.take() {
+            packet.resolve(result);
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// A way to resolve the task with `TaskError::TimedOut` while it runs.
+    pub(crate) fn deadline(&self) -> Deadline<T> {
+        Deadline {
+            packet: Arc::clone(self.packet.as_ref().unwrap()),
+            token: self.token.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    fn drop(&mut self) {
+        if let Some(packet) = self.packet.take() {
+            packet.resolve(Err(TaskError::Dropped));
+        }
+    }
+}
+
+/// Times out a running task, from `Completer::deadline`.
+pub(crate) struct Deadline<T> {
+    packet: Arc<Packet<T>>,
+    token: CancellationToken,
+}
+
+impl<T> Deadline<T> {
+    /// Resolves the task with `TaskError::TimedOut` and cancels its token,
+    /// unless it has already finished. Returns whether it timed out.
+    pub(crate) fn expire(self) -> bool {
+        if !self.packet.resolve(Err(TaskError::TimedOut)) {
+            return false;
+        }
+        self.token.cancel();
+        true
+    }
+}
+
+/// An owned handle to the result of a job submitted with `ThreadPool::submit`.
+pub struct TaskHandle<T> {
+    packet: Arc<Packet<T>>,
+    token: CancellationToken,
+}
+
+impl<T> TaskHandle<T> {
+    /// Cancels the task. If it hasn't started, it is skipped and `join`
+    /// returns `TaskError::Cancelled`. If it's running, it is up to the job
+    /// to notice through its `CancellationToken`.
+    pub fn cancel(&self) {
+        self.token.cancel();
+    }
+
+    /// The token shared with the job, for example to cancel several tasks
+    /// together.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Blocks until the job has finished and returns its result.
+    ///
+    /// On a worker thread, the worker runs other queued jobs while it waits,
+    /// so a job can wait on jobs it submitted to its own pool without
+    /// deadlocking it.
+    pub fn join(self) -> Result<T, TaskError> {
+        worker::help_until(|| self.is_finished());
+        let mut result = self.packet.result.lock().unwrap();
+        loop {
+            if let Some(result) = result.take() {
+                return result;
+            }
+            result = self.packet.done.wait(result).unwrap();
+        }
+    }
+
+    /// Returns the result if the job has finished, or gives the handle back.
+    pub fn try_join(self) -> Result<Result<T, TaskError>, TaskHandle<T>> {
+        let result = self.packet.result.lock().unwrap().take();
+        result.ok_or(self)
+    }
+
+    /// Like `join`, but gives the handle back if the job hasn't finished
+    /// within `timeout`. Like `join`, it runs other jobs while it waits on a
+    /// worker thread, which can make it return late.
+    pub fn join_timeout(self, timeout: Duration) -> Result<Result<T, TaskError>, TaskHandle<T>> {
+        let deadline = Instant::now() + timeout;
+        worker::help_until(|| self.is_finished() || Instant::now() >= deadline);
+        let mut result = self.packet.result.lock().unwrap();
+        loop {
+            if let Some(result) = result.take() {
+                return Ok(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                drop(result);
+                return Err(self);
+            }
+            result = self
+                .packet
+                .done
+                .wait_timeout(result, deadline - now)
+                .unwrap()
+                .0;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.result.lock().unwrap().is_some()
+    }
+}
+
+/// Awaiting a handle waits for the job without blocking the thread, so
+/// async code can hand work to the pool.
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, TaskError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut result = self.packet.result.lock().unwrap();
+        match result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                *self.packet.waker.lock().unwrap() = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for TaskHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskHandle")
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+pub(crate) fn task<T>() -> (Completer<T>, TaskHandle<T>) {
+    let packet = Arc::new(Packet {
+        result: Mutex::new(None),
+        done: Condvar::new(),
+        resolved: AtomicBool::new(false),
+        waker: Mutex::new(None),
+    });
+    let token = CancellationToken::new();
+    (
+        Completer {
+            packet: Some(Arc::clone(&packet)),
+            token: token.clone(),
+        },
+        TaskHandle { packet, token },
+    )
+}
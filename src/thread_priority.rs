@@ -0,0 +1,133 @@
+//! OS scheduling settings for worker threads, with the `thread-priority`
+//! feature.
+
+use std::io;
+
+/// A Linux scheduling policy for worker threads. See `sched(7)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedPolicy {
+    /// The default time-sharing policy, `SCHED_OTHER`.
+    Other,
+    /// For CPU-bound batch work, `SCHED_BATCH`. Threads are slightly
+    /// disfavored when the scheduler picks what to run.
+    Batch,
+    /// For very low priority background work, `SCHED_IDLE`.
+    Idle,
+    /// Real-time first in, first out, `SCHED_FIFO`, at a priority from 1 to
+    /// 99. Usually needs `CAP_SYS_NICE`.
+    Fifo(u8),
+    /// Real-time round robin, `SCHED_RR`, at a priority from 1 to 99.
+    /// Usually needs `CAP_SYS_NICE`.
+    RoundRobin(u8),
+}
+
+/// The settings `ThreadPoolBuilder` applies to each worker thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct ThreadPriority {
+    pub(crate) policy: Option<SchedPolicy>,
+    pub(crate) nice: Option<i32>,
+}
+
+impl ThreadPriority {
+    /// Applies the settings to the calling thread. The policy goes first,
+    /// since switching policy can reset the nice value.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(policy) = self.policy {
+            sys::set_policy(policy)?;
+        }
+        if let Some(nice) = self.nice {
+            sys::set_nice(nice)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::io;
+
+    use super::SchedPolicy;
+    use crate::os::thread_id;
+
+    pub(super) fn set_policy(policy: SchedPolicy) -> io::Result<()> {
+        let (policy, priority) = match policy {
+            SchedPolicy::Other => (libc::SCHED_OTHER, 0),
+            SchedPolicy::Batch => (libc::SCHED_BATCH, 0),
+            SchedPolicy::Idle => (libc::SCHED_IDLE, 0),
+            SchedPolicy::Fifo(priority) => (libc::SCHED_FIFO, priority),
+            SchedPolicy::RoundRobin(priority) => (libc::SCHED_RR, priority),
+        };
+        let param = libc::sched_param {
+            sched_priority: priority.into(),
+        };
+        // SAFETY: `param` is a valid sched_param.
+        if unsafe { libc::sched_setscheduler(thread_id(), policy, &param) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub(super) fn set_nice(nice: i32) -> io::Result<()> {
+        // On Linux the nice value belongs to the thread, not the process.
+        // SAFETY: setpriority takes no pointers.
+        let result =
+            unsafe { libc::setpriority(libc::PRIO_PROCESS, thread_id() as libc::id_t, nice) };
+        if result != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod sys {
+    use std::io;
+
+    use super::SchedPolicy;
+
+    fn unsupported() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            "thread priorities are only supported on Linux",
+        )
+    }
+
+    pub(super) fn set_policy(_policy: SchedPolicy) -> io::Result<()> {
+        Err(unsupported())
+    }
+
+    pub(super) fn set_nice(_nice: i32) -> io::Result<()> {
+        Err(unsupported())
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::{os, SchedPolicy, ThreadPoolBuilder};
+
+    #[test]
+    fn workers_run_with_the_configured_nice_value() {
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .sched_policy(SchedPolicy::Batch)
+            .nice(5)
+            .build()
+            .unwrap();
+        let (policy, nice) = pool
+            .submit(|| {
+                let tid = os::thread_id();
+                // SAFETY: neither call takes pointers.
+                unsafe {
+                    let policy = libc::sched_getscheduler(tid);
+                    (
+                        policy,
+                        libc::getpriority(libc::PRIO_PROCESS, tid as libc::id_t),
+                    )
+                }
+            })
+            .join()
+            .unwrap();
+        assert_eq!(policy, libc::SCHED_BATCH);
+        assert_eq!(nice, 5);
+    }
+}
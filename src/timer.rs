@@ -0,0 +1,411 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::Shared;
+
+/// A handle to a job scheduled with `ThreadPool::schedule_after` and
+/// friends.
+///
+/// Dropping the handle doesn't cancel the job.
+#[derive(Debug, Clone)]
+pub struct ScheduleHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ScheduleHandle {
+    /// Stops the job from running again. A run that has already started
+    /// finishes normally.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, atomic::Ordering::SeqCst);
+    }
+
+    /// Whether `cancel` has been called, or the schedule ended because the
+    /// pool shut down or a periodic run panicked.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(atomic::Ordering::SeqCst)
+    }
+}
+
+/// Returned by `ThreadPool::schedule_after` and friends when a job can't be
+/// scheduled.
+#[derive(Debug)]
+pub enum ScheduleError {
+    /// The pool has been shut down.
+    Shutdown,
+    /// The operating system refused to spawn the timer thread.
+    Spawn(io::Error),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScheduleError::Shutdown => f.write_str("the thread pool has been shut down"),
+            ScheduleError::Spawn(err) => write!(f, "failed to spawn timer thread: {}", err),
+        }
+    }
+}
+
+impl Error for ScheduleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ScheduleError::Shutdown => None,
+            ScheduleError::Spawn(err) => Some(err),
+        }
+    }
+}
+
+/// How a periodic job's next run is timed.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Period {
+    /// Every `Duration` after the first run, however long runs take.
+    FixedRate(Duration),
+    /// `Duration` after the previous run finished.
+    FixedDelay(Duration),
+}
+
+pub(crate) enum Task {
+    Once(Box<dyn FnOnce() + Send + 'static>),
+    Repeat(Box<dyn FnMut() + Send + 'static>, Period),
+    /// Runs on the timer thread itself rather than on a worker, so it fires
+    /// on time even when every worker is busy. Must be quick.
+    OnTimer(Box<dyn FnOnce() + Send + 'static>),
+}
+
+struct Entry {
+    due: Instant,
+    /// Breaks ties between entries due at the same time, first come first
+    /// served.
+    seq: u64,
+    task: Task,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Entry) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Entry) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Entry {
+    fn cmp(&self, other: &Entry) -> Ordering {
+        (self.due, self.seq).cmp(&(other.due, other.seq))
+    }
+}
+
+#[derive(Default)]
+struct Schedule {
+    entries: BinaryHeap<Reverse<Entry>>,
+    next_seq: u64,
+    stopped: bool,
+}
+
+/// Holds scheduled jobs until they're due, then submits them to the pool.
+///
+/// The timer thread is started the first time a job is scheduled, and
+/// stopped when the pool shuts down. Jobs still waiting then are dropped.
+///
+/// The thread must never wait for room in the pool's queue: it also expires
+/// the deadlines of `execute_with_timeout`, and a job stuck past its
+/// deadline may be what keeps the queue full.
+pub(crate) struct Timer {
+    schedule: Mutex<Schedule>,
+    wake: Condvar,
+    thread: Mutex<Option<thread::JoinHandle<()>>>,
+}
+
+impl Timer {
+    pub(crate) fn start(shared: Weak<Shared>, pool_name: &str) -> io::Result<Arc<Timer>> {
+        let timer = Arc::new(Timer {
+            schedule: Mutex::new(Schedule::default()),
+            wake: Condvar::new(),
+            thread: Mutex::new(None),
+        });
+        let thread = thread::Builder::new()
+            .name(format!("{}-timer", pool_name))
+            .spawn({
+                let timer = Arc::clone(&timer);
+                move || timer.run(shared)
+            })?;
+        *timer.thread.lock().unwrap() = Some(thread);
+        Ok(timer)
+    }
+
+    pub(crate) fn schedule(&self, due: Instant, task: Task) -> ScheduleHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        self.push(due, task, Arc::clone(&cancelled));
+        ScheduleHandle { cancelled }
+    }
+
+    fn push(&self, due: Instant, task: Task, cancelled: Arc<AtomicBool>) {
+        let mut schedule = self.schedule.lock().unwrap();
+        if schedule.stopped {
+            cancelled.store(true, atomic::Ordering::SeqCst);
+            return;
+        }
+        let seq = schedule.next_seq;
+        schedule.next_seq += 1;
+        schedule.entries.push(Reverse(Entry {
+            due,
+            seq,
+            task,
+            cancelled,
+        }));
+        self.wake.notify_one();
+    }
+
+    /// Drops every waiting job and tells the timer thread to exit.
+    pub(crate) fn stop(&self) {
+        let mut schedule = self.schedule.lock().unwrap();
+        schedule.stopped = true;
+        for Reverse(entry) in schedule.entries.drain() {
+            entry.cancelled.store(true, atomic::Ordering::SeqCst);
+        }
+        self.wake.notify_one();
+    }
+
+    pub(crate) fn join(&self) {
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn run(self: Arc<Self>, shared: Weak<Shared>) {
+        while let Some(entry) = self.next_due() {
+            if entry.cancelled.load(atomic::Ordering::SeqCst) {
+                continue;
+            }
+            let entry = match entry.task {
+                Task::OnTimer(f) => {
+                    f();
+                    continue;
+                }
+                task => Entry { task, ..entry },
+            };
+            let Some(shared) = shared.upgrade() else {
+                break;
+            };
+            let due = Due {
+                timer: Arc::clone(&self),
+                entry: Some(entry),
+            };
+            // If the pool no longer takes jobs, dropping `due` ends the
+            // schedule.
+            let _ = shared.submit_resumed(move || due.run());
+        }
+    }
+
+    /// Waits for the earliest entry to come due, or returns `None` once the
+    /// timer is stopped.
+    fn next_due(&self) -> Option<Entry> {
+        let mut schedule = self.schedule.lock().unwrap();
+        loop {
+            if schedule.stopped {
+                return None;
+            }
+            let now = Instant::now();
+            match schedule.entries.peek() {
+                Some(Reverse(entry)) if entry.due <= now => {
+                    return schedule.entries.pop().map(|Reverse(entry)| entry);
+                }
+                Some(Reverse(entry)) => {
+                    let timeout = entry.due - now;
+                    schedule = self.wake.wait_timeout(schedule, timeout).unwrap().0;
+                }
+                None => schedule = self.wake.wait(schedule).unwrap(),
+            }
+        }
+    }
+
+    /// Runs a due entry on a worker, then schedules the next run if it's
+    /// periodic. A periodic job that panics isn't run again.
+    fn run_entry(&self, entry: Entry) {
+        if entry.cancelled.load(atomic::Ordering::SeqCst) {
+            return;
+        }
+        match entry.task {
+            Task::Once(f) | Task::OnTimer(f) => f(),
+            Task::Repeat(mut f, period) => {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(&mut f)) {
+                    entry.cancelled.store(true, atomic::Ordering::SeqCst);
+                    panic::resume_unwind(payload);
+                }
+                if entry.cancelled.load(atomic::Ordering::SeqCst) {
+                    return;
+                }
+                let due = match period {
+                    Period::FixedRate(period) => entry.due + period,
+                    Period::FixedDelay(delay) => Instant::now() + delay,
+                };
+                self.push(due, Task::Repeat(f, period), entry.cancelled);
+            }
+        }
+    }
+}
+
+/// A due entry queued on the pool. Ends the entry's schedule if it's
+/// dropped without running, as `shutdown_now` does with queued jobs.
+struct Due {
+    timer: Arc<Timer>,
+    entry: Option<Entry>,
+}
+
+impl Due {
+    fn run(mut self) {
+        let entry = self.entry.take().unwrap();
+        self.timer.run_entry(entry);
+    }
+}
+
+impl Drop for Due {
+    fn drop(&mut self) {
+        if let Some(entry) = self.entry.take() {
+            entry.cancelled.store(true, atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tests::block_worker;
+    use crate::{BackpressurePolicy, ScheduleError, ThreadPool, ThreadPoolBuilder};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn schedule_after_waits_for_the_delay() {
+        let pool = ThreadPool::new(1);
+        let start = Instant::now();
+        let (tx, rx) = mpsc::channel();
+        pool.schedule_after(Duration::from_millis(30), move || tx.send(()).unwrap())
+            .unwrap();
+        rx.recv().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn cancelled_jobs_never_run() {
+        let pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = pool
+            .schedule_after(Duration::from_millis(20), move || tx.send(()).unwrap())
+            .unwrap();
+        handle.cancel();
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+
+    #[test]
+    fn periodic_jobs_repeat_until_cancelled() {
+        let pool = ThreadPool::new(2);
+        let rate = Arc::new(AtomicUsize::new(0));
+        let delay = Arc::new(AtomicUsize::new(0));
+        let every = pool
+            .schedule_every(Duration::from_millis(5), {
+                let rate = Arc::clone(&rate);
+                move || {
+                    rate.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        let fixed_delay = pool
+            .schedule_with_fixed_delay(Duration::from_millis(5), {
+                let delay = Arc::clone(&delay);
+                move || {
+                    delay.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        while rate.load(Ordering::SeqCst) < 3 || delay.load(Ordering::SeqCst) < 3 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        every.cancel();
+        fixed_delay.cancel();
+        pool.wait_idle();
+        let runs = (rate.load(Ordering::SeqCst), delay.load(Ordering::SeqCst));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(
+            (rate.load(Ordering::SeqCst), delay.load(Ordering::SeqCst)),
+            runs
+        );
+    }
+
+    #[test]
+    fn scheduling_fails_once_the_pool_shuts_down() {
+        let mut pool = ThreadPool::new(1);
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || {
+            thread::sleep(Duration::from_millis(20));
+            let pool = ThreadPool::current().unwrap();
+            let scheduled = pool.schedule_after(Duration::ZERO, || {});
+            tx.send(matches!(scheduled, Err(ScheduleError::Shutdown)))
+                .unwrap();
+        });
+        pool.join().unwrap();
+        assert!(rx.recv().unwrap());
+        let scheduled = pool.schedule_every(Duration::from_millis(1), || {});
+        assert!(matches!(scheduled, Err(ScheduleError::Shutdown)));
+    }
+
+    #[test]
+    fn due_jobs_get_past_a_full_queue() {
+        for policy in [
+            BackpressurePolicy::Block,
+            BackpressurePolicy::Reject,
+            BackpressurePolicy::DropOldest,
+        ] {
+            let pool = ThreadPoolBuilder::new()
+                .num_threads(1)
+                .queue_capacity(1)
+                .backpressure(policy)
+                .build()
+                .unwrap();
+            let release = block_worker(&pool);
+            pool.execute(|| {});
+            let runs = Arc::new(AtomicUsize::new(0));
+            let every = pool
+                .schedule_every(Duration::from_millis(1), {
+                    let runs = Arc::clone(&runs);
+                    move || {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                    }
+                })
+                .unwrap();
+            let (tx, rx) = mpsc::channel();
+            pool.schedule_after(Duration::from_millis(5), move || tx.send(()).unwrap())
+                .unwrap();
+            thread::sleep(Duration::from_millis(20));
+            if policy == BackpressurePolicy::DropOldest {
+                // Makes room by evicting the queued `execute` job only.
+                pool.execute(|| {});
+            }
+            release.send(()).unwrap();
+
+            rx.recv_timeout(Duration::from_secs(5))
+                .unwrap_or_else(|_| panic!("the timer got stuck with {:?}", policy));
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while runs.load(Ordering::SeqCst) < 3 {
+                assert!(Instant::now() < deadline, "{:?}", policy);
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!every.is_cancelled(), "{:?}", policy);
+            every.cancel();
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::cell::{Cell, RefCell};
+#[cfg(any(feature = "affinity", feature = "thread-priority"))]
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crossbeam_deque::Worker as Deque;
+
+#[cfg(feature = "affinity")]
+use crate::affinity;
+use crate::metrics::WorkerStats;
+#[cfg(any(feature = "affinity", feature = "thread-priority"))]
+use crate::ThreadPoolBuilder;
+use crate::{instrument, BuildError, Job, PoolState, Shared};
+
+/// How many times an idle worker yields and searches again before parking.
+/// Parking and waking cost a syscall each, which dominates for small jobs.
+const YIELD_ROUNDS: usize = 16;
+
+/// How long a worker helping out in a blocking wait parks before checking
+/// again whether the wait is over. Nothing wakes it when that happens.
+const HELP_PARK: Duration = Duration::from_millis(1);
+
+pub(crate) struct Worker {
+    pub(crate) thread: Option<thread::JoinHandle<()>>,
+    #[cfg(feature = "affinity")]
+    pub(crate) id: usize,
+    #[cfg(feature = "affinity")]
+    pub(crate) thread_id: affinity::ThreadId,
+}
+
+/// What a worker thread needs to reach its pool and its own deque from
+/// inside a job.
+struct WorkerContext {
+    shared: Arc<Shared>,
+    id: usize,
+    index: usize,
+    local: Deque<Job>,
+    stats: Arc<WorkerStats>,
+}
+
+thread_local! {
+    static WORKER: RefCell<Option<WorkerContext>> = const { RefCell::new(None) };
+    /// Set by a task that catches its own panic to hand it to its caller,
+    /// so the job running it still counts as panicked.
+    static CAUGHT_PANIC: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How a job ended.
+pub(crate) enum Outcome {
+    Completed,
+    /// A task inside the job panicked and delivered the panic to whoever
+    /// waits for it, through a `TaskHandle`, scope or graph.
+    Caught,
+    /// The panic escaped the job.
+    Panicked,
+}
+
+/// Runs a job on the calling thread and tells how it ended. Jobs run from
+/// inside this one while it waits are told apart from it.
+pub(crate) fn run_job(run: Box<dyn FnOnce() + Send + 'static>) -> Outcome {
+    let outer = CAUGHT_PANIC.replace(false);
+    let result = panic::catch_unwind(AssertUnwindSafe(run));
+    let caught = CAUGHT_PANIC.replace(outer);
+    match result {
+        Err(_) => Outcome::Panicked,
+        Ok(()) if caught => Outcome::Caught,
+        Ok(()) => Outcome::Completed,
+    }
+}
+
+/// Records that the running job caught a panic, for `run_job`.
+pub(crate) fn report_caught_panic() {
+    CAUGHT_PANIC.set(true);
+}
+
+impl Worker {
+    /// Spawns a worker thread. With the `affinity` or `thread-priority`
+    /// features, the thread applies the builder's OS settings to itself
+    /// before taking any job, and this waits to hear whether that worked.
+    pub(crate) fn new(
+        id: usize,
+        builder: thread::Builder,
+        shared: Arc<Shared>,
+    ) -> Result<Worker, BuildError> {
+        let (index, local) = shared.queue.register();
+        shared.live_workers.add(1);
+        shared.working.add(1);
+        let stats = shared.metrics.add_worker(id);
+        let context = WorkerContext {
+            shared: Arc::clone(&shared),
+            id,
+            index,
+            local,
+            stats: Arc::clone(&stats),
+        };
+        let abandon = || {
+            shared.queue.retire(index);
+            stats.exited();
+            shared.live_workers.sub(1);
+            shared.working.sub(1);
+        };
+
+        #[cfg(any(feature = "affinity", feature = "thread-priority"))]
+        let (started_tx, started) = std::sync::mpsc::sync_channel(1);
+        let thread = builder.spawn(move || {
+            #[cfg(any(feature = "affinity", feature = "thread-priority"))]
+            {
+                let configured = configure(&context.shared.builder, id);
+                let failed = configured.is_err();
+                let _ = started_tx.send(configured);
+                if failed {
+                    return;
+                }
+            }
+            WORKER.with(|worker| *worker.borrow_mut() = Some(context));
+            WORKER.with(|worker| worker.borrow().as_ref().unwrap().run());
+        });
+        let thread = match thread {
+            Ok(thread) => thread,
+            Err(err) => {
+                abandon();
+                return Err(BuildError::Spawn(err));
+            }
+        };
+
+        #[cfg(any(feature = "affinity", feature = "thread-priority"))]
+        #[cfg_attr(not(feature = "affinity"), allow(unused_variables))]
+        let started = match started.recv() {
+            Ok(Ok(started)) => started,
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                abandon();
+                return Err(err);
+            }
+            Err(_) => {
+                let _ = thread.join();
+                abandon();
+                let err = io::Error::other("worker thread panicked while starting");
+                return Err(BuildError::Spawn(err));
+            }
+        };
+        Ok(Worker {
+            thread: Some(thread),
+            #[cfg(feature = "affinity")]
+            id,
+            #[cfg(feature = "affinity")]
+            thread_id: started.thread_id,
+        })
+    }
+}
+
+/// What a new worker thread reports back once it has configured itself.
+#[cfg(any(feature = "affinity", feature = "thread-priority"))]
+struct Started {
+    #[cfg(feature = "affinity")]
+    thread_id: affinity::ThreadId,
+}
+
+/// Applies the builder's OS settings to the calling worker thread.
+#[cfg(any(feature = "affinity", feature = "thread-priority"))]
+#[cfg_attr(not(feature = "affinity"), allow(unused_variables))]
+fn configure(builder: &ThreadPoolBuilder, id: usize) -> Result<Started, BuildError> {
+    #[cfg(feature = "thread-priority")]
+    builder
+        .thread_priority
+        .apply()
+        .map_err(BuildError::ThreadPriority)?;
+    #[cfg(feature = "affinity")]
+    if let Some(pinning) = &builder.pinning {
+        pinning.pin(id).map_err(BuildError::Affinity)?;
+    }
+    Ok(Started {
+        #[cfg(feature = "affinity")]
+        thread_id: affinity::thread_id(),
+    })
+}
+
+impl WorkerContext {
+    fn run(&self) {
+        let shared = &self.shared;
+        shared.observe(|observer| observer.on_worker_spawn(self.id));
+        let mut idle_rounds = 0;
+        loop {
+            let state = shared.state();
+            if state == PoolState::Running && shared.claim_retirement(false) {
+                return self.retire();
+            }
+            if state >= PoolState::Stopping {
+                shared.observe(|observer| observer.on_terminate(self.id));
+                break;
+            }
+            let draining =
+                state == PoolState::ShuttingDown && shared.submitting.load(Ordering::SeqCst) == 0;
+
+            if let Some(job) = shared.queue.find(self.index, &self.local) {
+                idle_rounds = 0;
+                self.execute(job);
+            } else if draining {
+                shared.observe(|observer| observer.on_terminate(self.id));
+                break;
+            } else if idle_rounds < YIELD_ROUNDS || state != PoolState::Running {
+                idle_rounds += 1;
+                thread::yield_now();
+            } else {
+                idle_rounds = 0;
+                let timed_out = shared.queue.park(
+                    || shared.state() == PoolState::Running && !shared.has_surplus_workers(),
+                    shared.keep_alive,
+                );
+                if timed_out && shared.claim_retirement(true) {
+                    return self.retire();
+                }
+            }
+        }
+        shared.observe(|observer| observer.on_worker_exit(self.id));
+        self.stats.exited();
+        shared.live_workers.sub(1);
+        shared.working.sub(1);
+    }
+
+    /// Exits the worker after `Shared::claim_retirement` picked it.
+    fn retire(&self) {
+        self.shared.queue.retire(self.index);
+        self.shared
+            .observe(|observer| observer.on_worker_exit(self.id));
+        self.stats.exited();
+        self.shared.working.sub(1);
+    }
+
+    /// Runs jobs from inside a job until `done` returns true. If nothing is
+    /// queued, whatever the caller waits for is running elsewhere, so this
+    /// yields and then parks like an idle worker.
+    ///
+    /// Once the pool is stopping, this only waits: the jobs still queued
+    /// belong to `shutdown_now`.
+    fn help_until(&self, done: impl Fn() -> bool) {
+        let shared = &self.shared;
+        let mut idle_rounds = 0;
+        while !done() {
+            let job = match shared.state() {
+                PoolState::Running | PoolState::ShuttingDown => {
+                    shared.queue.find(self.index, &self.local)
+                }
+                PoolState::Stopping | PoolState::Terminated => None,
+            };
+            if let Some(job) = job {
+                idle_rounds = 0;
+                self.execute(job);
+            } else if idle_rounds < YIELD_ROUNDS {
+                idle_rounds += 1;
+                thread::yield_now();
+            } else {
+                // Parked here, the worker can't finish its job until `done`.
+                shared.working.sub(1);
+                shared.queue.park(|| !done(), Some(HELP_PARK));
+                shared.working.add(1);
+            }
+        }
+    }
+
+    fn execute(&self, job: Job) {
+        let shared = &self.shared;
+        let start = Instant::now();
+        let queue_wait = start - job.queued_at;
+        let _span = instrument::enter_job(self.id, job.id, queue_wait);
+        shared.metrics.job_started(queue_wait);
+        // The queue may have backed up while this worker was picking the
+        // job up and still looked idle to submitters.
+        shared.grow_if_backed_up();
+        shared.observe(|observer| observer.on_job_start(self.id));
+        let outcome = run_job(job.run);
+        if !matches!(outcome, Outcome::Completed) {
+            shared.observe(|observer| observer.on_job_panic(self.id));
+        }
+        shared.record_outcome(outcome);
+        let duration = start.elapsed();
+        shared.metrics.job_finished(&self.stats, duration);
+        shared.observe(|observer| observer.on_job_end(self.id, duration));
+        shared.unfinished.sub(1);
+    }
+}
+
+/// The pool the current thread is a worker of, if any.
+pub(crate) fn current() -> Option<Arc<Shared>> {
+    WORKER.with(|worker| {
+        let worker = worker.borrow();
+        worker.as_ref().map(|worker| Arc::clone(&worker.shared))
+    })
+}
+
+/// If the current thread is a worker, runs its pool's queued jobs until
+/// `done` returns true, so that a job blocked waiting on another can't hold
+/// up the pool. Returns false straight away on any other thread.
+pub(crate) fn help_until(done: impl Fn() -> bool) -> bool {
+    WORKER.with(|worker| match &*worker.borrow() {
+        Some(worker) => {
+            worker.help_until(done);
+            true
+        }
+        None => false,
+    })
+}
+
+/// Calls `f` with the current thread's deque if it is a worker of `shared`,
+/// or with `None` otherwise.
+pub(crate) fn with_local<R>(shared: &Arc<Shared>, f: impl FnOnce(Option<&Deque<Job>>) -> R) -> R {
+    WORKER.with(|worker| match &*worker.borrow() {
+        Some(worker) if Arc::ptr_eq(&worker.shared, shared) => f(Some(&worker.local)),
+        _ => f(None),
+    })
+}